@@ -1,5 +1,10 @@
-use crate::csa::{Calendar, Connection, Stop, StopId, Transfer};
-use std::collections::HashMap;
+use crate::csa::{
+    Calendar, Connection, ServiceKind, StaySeated, Stop, StopId, Transfer, TransferMode, TripId,
+    WALKING_SPEED_M_S, chord2_to_meters, meters_to_chord2, to_unit,
+};
+use chrono::TimeDelta;
+use kiddo::{KdTree, SquaredEuclidean};
+use std::collections::{HashMap, HashSet};
 
 pub trait CsaAdapter {
     type Error;
@@ -7,11 +12,168 @@ pub trait CsaAdapter {
     /// Returns a stable, deduplicated list of stops (in CSA order).
     fn stops(&self) -> Result<HashMap<StopId, Stop>, Self::Error>;
 
-    /// Returns all connections (any order); the builder will sort by departure.
+    /// Returns all connections, each trip's in the order it makes them; the builder
+    /// will sort by departure.
     fn connections(&self) -> Result<Vec<Connection>, Self::Error>;
 
     /// Returns footpath/transfer graph.
     fn transfers(&self) -> Result<HashMap<StopId, Vec<Transfer>>, Self::Error>;
 
     fn calendar(&self) -> Result<Calendar, Self::Error>;
+
+    /// Returns pairs of trips that passengers can stay on board between.
+    fn stay_seated(&self) -> Result<Vec<StaySeated>, Self::Error> {
+        Ok(vec![])
+    }
+}
+
+/// Combines several adapters into one network. Each source's stops and trips are
+/// renumbered past those of the sources before it, and stops from different sources
+/// within `link_radius` metres of each other are joined by walking transfers.
+/// Connections of the `excluded` kinds are left out altogether.
+pub struct MergedAdapter {
+    link_radius: f64,
+    excluded: HashSet<ServiceKind>,
+    stops: HashMap<StopId, Stop>,
+    /// Index of the source each stop came from.
+    sources: HashMap<StopId, usize>,
+    connections: Vec<Connection>,
+    transfers: HashMap<StopId, Vec<Transfer>>,
+    stay_seated: Vec<StaySeated>,
+    calendar: Calendar,
+    next_stop: u64,
+    next_trip: u64,
+    source_count: usize,
+}
+
+impl MergedAdapter {
+    pub fn new(link_radius: f64, excluded: HashSet<ServiceKind>) -> Self {
+        Self {
+            link_radius,
+            excluded,
+            stops: HashMap::new(),
+            sources: HashMap::new(),
+            connections: vec![],
+            transfers: HashMap::new(),
+            stay_seated: vec![],
+            calendar: Calendar::default(),
+            next_stop: 0,
+            next_trip: 0,
+            source_count: 0,
+        }
+    }
+
+    /// Reads everything from `adapter`, so it doesn't need to outlive this call.
+    pub fn add<A: CsaAdapter>(&mut self, adapter: &A) -> Result<(), A::Error> {
+        let stop_offset = self.next_stop;
+        let trip_offset = self.next_trip;
+        let stop_id = |id: StopId| StopId::new(id.index() + stop_offset);
+        let trip_id = |id: TripId| TripId::new(id.index() + trip_offset);
+        let source = self.source_count;
+
+        for (id, stop) in adapter.stops()? {
+            let id = stop_id(id);
+            self.next_stop = self.next_stop.max(id.index() + 1);
+            self.stops.insert(id, stop);
+            self.sources.insert(id, source);
+        }
+
+        let connections = adapter.connections()?;
+        for c in connections
+            .into_iter()
+            .filter(|c| !self.excluded.contains(&c.service.kind))
+        {
+            let c = Connection {
+                trip_id: trip_id(c.trip_id),
+                from_stop_id: stop_id(c.from_stop_id),
+                to_stop_id: stop_id(c.to_stop_id),
+                ..c
+            };
+            self.next_trip = self.next_trip.max(c.trip_id.index() + 1);
+            self.connections.push(c);
+        }
+
+        for (from, transfers) in adapter.transfers()? {
+            self.transfers
+                .entry(stop_id(from))
+                .or_default()
+                .extend(transfers.into_iter().map(|t| Transfer {
+                    from_stop_id: stop_id(t.from_stop_id),
+                    to_stop_id: stop_id(t.to_stop_id),
+                    ..t
+                }));
+        }
+
+        for s in adapter.stay_seated()? {
+            self.stay_seated.push(StaySeated {
+                from_trip_id: trip_id(s.from_trip_id),
+                to_trip_id: trip_id(s.to_trip_id),
+                stop_id: stop_id(s.stop_id),
+                ..s
+            });
+        }
+
+        let calendar = adapter.calendar()?;
+        self.calendar.extend(calendar, |id| {
+            self.next_trip = self.next_trip.max(trip_id(id).index() + 1);
+            trip_id(id)
+        });
+
+        self.source_count += 1;
+        Ok(())
+    }
+
+    /// Walking transfers between nearby stops that came from different sources.
+    fn links(&self) -> impl Iterator<Item = Transfer> {
+        let mut tree: KdTree<f64, 3> = KdTree::new();
+        for (&id, s) in &self.stops {
+            tree.add(&to_unit(s.lat, s.lon), id.index());
+        }
+
+        let max_chord2 = meters_to_chord2(self.link_radius);
+        self.stops.iter().flat_map(move |(&from, s)| {
+            tree.within::<SquaredEuclidean>(&to_unit(s.lat, s.lon), max_chord2)
+                .into_iter()
+                .map(|n| (StopId::new(n.item), chord2_to_meters(n.distance)))
+                .filter(|(to, _)| self.sources[to] != self.sources[&from])
+                .map(move |(to, distance)| Transfer {
+                    from_stop_id: from,
+                    to_stop_id: to,
+                    transfer_time: TimeDelta::seconds((distance / WALKING_SPEED_M_S) as i64),
+                    validity: None,
+                    mode: TransferMode::Walk,
+                    priority: None,
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+}
+
+impl CsaAdapter for MergedAdapter {
+    type Error = anyhow::Error;
+
+    fn stops(&self) -> anyhow::Result<HashMap<StopId, Stop>> {
+        Ok(self.stops.clone())
+    }
+
+    fn connections(&self) -> anyhow::Result<Vec<Connection>> {
+        Ok(self.connections.clone())
+    }
+
+    fn transfers(&self) -> anyhow::Result<HashMap<StopId, Vec<Transfer>>> {
+        let mut transfers = self.transfers.clone();
+        for link in self.links() {
+            transfers.entry(link.from_stop_id).or_default().push(link);
+        }
+
+        Ok(transfers)
+    }
+
+    fn calendar(&self) -> anyhow::Result<Calendar> {
+        Ok(self.calendar.clone())
+    }
+
+    fn stay_seated(&self) -> anyhow::Result<Vec<StaySeated>> {
+        Ok(self.stay_seated.clone())
+    }
 }
@@ -1,105 +1,202 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{NaiveTime, Timelike};
 use itertools::Itertools;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{cmp::Reverse, collections::HashMap, fs::File, io::BufReader, path::Path, ptr};
+use tracing::warn;
 
 use crate::{
     adapters::CsaAdapter,
-    cif::CifTimetable,
-    csa::{Calendar, Connection, Service, Stop, StopId, Transfer, TripId},
+    cif::{
+        CifTimetable,
+        mca::{Association, AssociationCategory, Location, Schedule, ScheduleType, service_kind},
+        resolver::StopResolver,
+    },
+    csa::{
+        Calendar, Connection, InterchangeTime, Service, ServiceInfo, StaySeated, Stop, StopId,
+        Transfer, TripId,
+    },
 };
 
+/// A station location that takes precedence over the grid reference in the MSN, in the
+/// format of the `uk-railway-stations` dataset.
 #[derive(Deserialize, Clone)]
 pub struct StationInfo {
     #[serde(rename = "stationName")]
-    name: String,
+    pub name: String,
     #[serde(rename = "crsCode")]
     pub crs: String,
-    lat: f64,
+    pub lat: f64,
     #[serde(rename = "long")]
-    lon: f64,
+    pub lon: f64,
+}
+
+/// Reads a JSON array of station locations to use instead of those in the MSN.
+pub fn read_station_overrides<P: AsRef<Path>>(path: P) -> Result<Vec<StationInfo>> {
+    let file = File::open(&path).with_context(|| format!("opening {:?}", path.as_ref()))?;
+    let stations = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("parsing {:?}", path.as_ref()))?;
+    Ok(stations)
+}
+
+/// Reads a JSON object mapping TIPLOCs to the CRS codes of the stations they belong
+/// to, for TIPLOCs the MSN doesn't cover.
+pub fn read_tiploc_mappings<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>> {
+    let file = File::open(&path).with_context(|| format!("opening {:?}", path.as_ref()))?;
+    let mappings = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("parsing {:?}", path.as_ref()))?;
+    Ok(mappings)
 }
 
 pub struct CifAdapter<'a> {
     timetable: &'a CifTimetable,
-    schedule_to_trip_id: HashMap<String, TripId>,
-    crs_to_stop_id: HashMap<String, StopId>,
-    tiploc_to_stop_id: HashMap<String, StopId>,
-    stops: HashMap<StopId, Stop>,
+    resolver: StopResolver,
 }
 
 impl<'a> CifAdapter<'a> {
-    pub fn new(timetable: &'a CifTimetable) -> Result<Self> {
-        let station_str = include_str!("../../uk-railway-stations/stations.json");
-        let station_info: Vec<StationInfo> = serde_json::from_str(station_str)?;
-        let station_info: HashMap<String, StationInfo> = station_info
-            .into_iter()
-            .map(|s| (s.crs.clone(), s))
-            .collect();
-
-        let mut crs_to_stop_id = HashMap::new();
-        let mut tiploc_to_stop_id = HashMap::new();
-        let mut stops = HashMap::new();
-        let mut schedule_to_trip_id = HashMap::new();
-
-        for (i, s) in timetable
-            .stations
-            .iter()
-            .filter(|s| station_info.contains_key(&s.crs))
-            .enumerate()
-        {
-            let id = StopId::new(i as u64);
-            let crs = s.crs.clone();
-            let tiploc = s.tiploc.clone();
+    /// Builds stops from the MSN, locating each by its grid reference unless it appears
+    /// in `station_overrides`. Stations with neither are left out, as are the calls at
+    /// TIPLOCs that resolve to no stop, which are logged. `tiploc_mappings` maps
+    /// TIPLOCs missing from the MSN to CRS codes.
+    pub fn new(
+        timetable: &'a CifTimetable,
+        station_overrides: &[StationInfo],
+        tiploc_mappings: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let mut resolver = StopResolver::new(
+            &timetable.stations,
+            &timetable.aliases,
+            station_overrides,
+            tiploc_mappings,
+        );
 
-            let info = &station_info[&crs];
-            let name = info.name.clone();
-            let lat = info.lat;
-            let lon = info.lon;
-
-            let stop = Stop::new(name, lat, lon);
-
-            stops.insert(id, stop);
-            tiploc_to_stop_id.insert(tiploc, id);
-            crs_to_stop_id.insert(crs, id);
+        // Interchange times between particular operators override the MSN's
+        for i in &timetable.interchanges {
+            if let Some(stop) = resolver.crs(&i.crs).and_then(|id| resolver.stop_mut(id)) {
+                stop.interchange_times.push(InterchangeTime {
+                    arriving_operator: i.arriving_operator.clone(),
+                    departing_operator: i.departing_operator.clone(),
+                    time: i.time,
+                });
+            }
         }
 
-        for (i, schedule) in timetable.schedules.iter().enumerate() {
-            let trip_id = TripId::new(i as u64);
-            schedule_to_trip_id.insert(schedule.id.clone(), trip_id);
+        let unmapped = timetable
+            .schedules
+            .iter()
+            .flat_map(|s| &s.locations)
+            .map(|loc| loc.id())
+            .filter(|tiploc| resolver.tiploc(tiploc).is_none())
+            .counts();
+        if !unmapped.is_empty() {
+            warn!(
+                "{} TIPLOCs with {} calls don't resolve to a stop",
+                unmapped.len(),
+                unmapped.values().sum::<usize>()
+            );
+        }
+        for (tiploc, count) in unmapped.iter().sorted_by_key(|&(_, &n)| Reverse(n)) {
+            warn!("Unmapped TIPLOC {tiploc} has {count} calls");
         }
 
         Ok(Self {
             timetable,
-            schedule_to_trip_id,
-            crs_to_stop_id,
-            tiploc_to_stop_id,
-            stops,
+            resolver,
         })
     }
 }
 
+/// Converts each location's arrival and departure times to seconds since the start of
+/// the service day, adding a day whenever the train runs past midnight.
+fn service_times(locations: &[Location]) -> Vec<(Option<u32>, Option<u32>)> {
+    const SECONDS_PER_DAY: u32 = 86_400;
+
+    let mut day = 0;
+    let mut previous = 0;
+    let mut to_service_time = |time: NaiveTime| {
+        let mut seconds = day + time.num_seconds_from_midnight();
+        if seconds < previous {
+            day += SECONDS_PER_DAY;
+            seconds += SECONDS_PER_DAY;
+        }
+        previous = seconds;
+        seconds
+    };
+
+    locations
+        .iter()
+        .map(|loc| {
+            let arrival = loc.arrival_time().map(&mut to_service_time);
+            let departure = loc.departure_time().map(&mut to_service_time);
+            (arrival, departure)
+        })
+        .collect()
+}
+
+/// The details of the train in force on departure from each location, taking into
+/// account any changes en route.
+fn service_details(schedule: &Schedule) -> Vec<ServiceInfo> {
+    let non_empty = |s: &str| Some(s.to_owned()).filter(|s| !s.is_empty());
+    let mut current = ServiceInfo {
+        operator: schedule.operator.clone(),
+        category: non_empty(&schedule.category),
+        service_code: non_empty(&schedule.service_code),
+        kind: schedule.service_kind(),
+    };
+
+    schedule
+        .locations
+        .iter()
+        .map(|loc| {
+            if let Some(change) = loc.change() {
+                current = ServiceInfo {
+                    category: non_empty(&change.category),
+                    service_code: non_empty(&change.service_code),
+                    kind: service_kind(&change.category, &schedule.status),
+                    ..current.clone()
+                };
+            }
+            current.clone()
+        })
+        .collect()
+}
+
 impl<'a> CsaAdapter for CifAdapter<'a> {
     type Error = anyhow::Error;
 
     fn stops(&self) -> Result<HashMap<StopId, Stop>> {
-        Ok(self.stops.clone())
+        Ok(self.resolver.stops().clone())
     }
 
+    /// Every schedule is its own trip. Where schedules share a train UID, each one is
+    /// cancelled on the dates a higher precedence schedule applies, so that only one
+    /// version of the train runs on any date.
     fn calendar(&self) -> Result<Calendar> {
         let mut services: HashMap<TripId, Vec<Service>> = HashMap::new();
         let mut cancellations: HashMap<TripId, Vec<Service>> = HashMap::new();
 
-        for schedule in self.timetable.schedules.iter() {
-            let trip_id = self.schedule_to_trip_id[&schedule.id];
-            let service = Service::new(schedule.start_date, schedule.end_date, schedule.days_run);
-            match schedule.trip_type {
-                crate::cif::mca::ScheduleType::Cancellation => {
-                    cancellations.entry(trip_id).or_default().push(service)
-                }
-                _ => {
-                    services.entry(trip_id).or_default().push(service);
+        let service = |s: &Schedule| Service::new(s.start_date, s.end_date, s.days_run);
+        let by_uid = self
+            .timetable
+            .schedules
+            .iter()
+            .enumerate()
+            .into_group_map_by(|(_, s)| s.id.as_str());
+
+        for variants in by_uid.values() {
+            for &(i, schedule) in variants {
+                if schedule.trip_type == ScheduleType::Cancellation {
+                    continue;
                 }
+
+                let trip_id = TripId::new(i as u64);
+                services.entry(trip_id).or_default().push(service(schedule));
+
+                let overridden = variants
+                    .iter()
+                    .filter(|(_, other)| other.trip_type > schedule.trip_type)
+                    .map(|(_, other)| service(other));
+                cancellations.entry(trip_id).or_default().extend(overridden);
             }
         }
 
@@ -114,35 +211,43 @@ impl<'a> CsaAdapter for CifAdapter<'a> {
         // with the tiploc_to_crs map in the timetable
         let mut connections = vec![];
 
-        for schedule in self.timetable.schedules.iter() {
-            let trip_id = self.schedule_to_trip_id[&schedule.id];
+        for (i, schedule) in self.timetable.schedules.iter().enumerate() {
+            let trip_id = TripId::new(i as u64);
 
             let locations: Vec<_> = schedule
                 .locations
                 .iter()
-                .filter(|loc| self.tiploc_to_stop_id.contains_key(&loc.id()))
+                .zip(service_times(&schedule.locations))
+                .zip(service_details(schedule))
+                .filter_map(|((loc, times), service)| {
+                    Some((self.resolver.tiploc(&loc.id())?, loc, times, service))
+                })
                 .collect();
 
             for locs in locations.windows(2) {
-                let from = &locs[0];
-                let to = &locs[1];
+                let (from_id, from, (_, departure_time), service) = &locs[0];
+                let (to_id, to, (arrival_time, _), _) = &locs[1];
 
-                let from_id = self.tiploc_to_stop_id[&from.id()];
-                let to_id = self.tiploc_to_stop_id[&to.id()];
+                // Consecutive calls at different TIPLOCs of the same station
+                if from_id == to_id {
+                    continue;
+                }
 
-                let departure_time = from
-                    .departure_time()
-                    .expect("Should only be origin or intermediate");
-                let arrival_time = to
-                    .arrival_time()
-                    .expect("Should only be intermediate or destination");
+                let departure_time = departure_time.expect("Should only be origin or intermediate");
+                let arrival_time =
+                    arrival_time.expect("Should only be intermediate or destination");
 
                 let connection = Connection {
                     trip_id,
-                    from_stop_id: from_id,
-                    to_stop_id: to_id,
+                    from_stop_id: *from_id,
+                    to_stop_id: *to_id,
                     departure_time,
                     arrival_time,
+                    can_board: from.picks_up(),
+                    can_alight: to.sets_down(),
+                    service: service.clone(),
+                    departure_platform: from.platform().map(str::to_owned),
+                    arrival_platform: to.platform().map(str::to_owned),
                 };
                 connections.push(connection);
             }
@@ -151,29 +256,196 @@ impl<'a> CsaAdapter for CifAdapter<'a> {
         Ok(connections)
     }
 
+    /// Passenger joins and divides, between every schedule of the two trains. The
+    /// calendar already ensures only one schedule of each runs on any date, and
+    /// association STP indicators take precedence in the same way as for schedules.
+    fn stay_seated(&self) -> Result<Vec<StaySeated>> {
+        let trips_by_uid = self
+            .timetable
+            .schedules
+            .iter()
+            .enumerate()
+            .into_group_map_by(|(_, s)| s.id.as_str());
+        let associations = self
+            .timetable
+            .associations
+            .iter()
+            .filter(|a| a.passenger && a.category != AssociationCategory::Next)
+            .into_group_map_by(|a| (&a.base_uid, &a.assoc_uid, &a.tiploc));
+
+        let service = |a: &Association| Service::new(a.start_date, a.end_date, a.days_run);
+        let mut stay_seated = vec![];
+
+        for variants in associations.values() {
+            for &association in variants {
+                if association.stp == ScheduleType::Cancellation {
+                    continue;
+                }
+                let Some(stop_id) = self.resolver.tiploc(&association.tiploc) else {
+                    continue;
+                };
+
+                let cancellations: Vec<_> = variants
+                    .iter()
+                    .filter(|other| other.stp > association.stp)
+                    .map(|&other| service(other))
+                    .collect();
+
+                let bases = trips_by_uid.get(association.base_uid.as_str());
+                let assocs = trips_by_uid.get(association.assoc_uid.as_str());
+                let pairs = bases
+                    .into_iter()
+                    .flatten()
+                    .cartesian_product(assocs.into_iter().flatten());
+
+                for (&(base, _), &(assoc, _)) in pairs {
+                    let (base, assoc) = (TripId::new(base as u64), TripId::new(assoc as u64));
+
+                    // Passengers on a dividing train carry on in the associated train,
+                    // and those on a joining train carry on in the base train
+                    let (from_trip_id, to_trip_id, day_offset, onward_dates) = match association
+                        .category
+                    {
+                        AssociationCategory::Divide => (base, assoc, association.day_offset, false),
+                        _ => (assoc, base, -association.day_offset, true),
+                    };
+
+                    stay_seated.push(StaySeated {
+                        from_trip_id,
+                        to_trip_id,
+                        stop_id,
+                        day_offset,
+                        onward_dates,
+                        services: vec![service(association)],
+                        cancellations: cancellations.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(stay_seated)
+    }
+
     fn transfers(&self) -> Result<HashMap<StopId, Vec<Transfer>>, Self::Error> {
-        // links contain origin and destination CRS, which can use the map from CRS to Stop ID
-        // They also contain a transfer time in minutes which can just be reused
-        let transfers = self
+        // Where links between the same stations can be used at the same time, the one
+        // with the best priority then applies, so only those links keep their priority
+        let links = self
             .timetable
             .links
             .iter()
-            .filter(|link| {
-                self.crs_to_stop_id.contains_key(&link.origin_crs)
-                    && self.crs_to_stop_id.contains_key(&link.dest_crs)
-            })
-            .map(|link| {
-                let from_stop_id = self.crs_to_stop_id[&link.origin_crs];
-                let to_stop_id = self.crs_to_stop_id[&link.dest_crs];
+            .into_group_map_by(|link| (&link.origin_crs, &link.dest_crs))
+            .into_values()
+            .flat_map(|group| {
+                group
+                    .iter()
+                    .map(|&link| {
+                        let competes = group
+                            .iter()
+                            .any(|&other| !ptr::eq(other, link) && other.overlaps(link));
+                        (link, competes.then_some(link.priority))
+                    })
+                    .collect::<Vec<_>>()
+            });
+        let fixed_links = self.timetable.fixed_links.iter().map(|link| (link, None));
+
+        // links contain origin and destination CRS, which the resolver maps to stops
+        // They also contain a transfer time in minutes which can just be reused
+        let transfers = links
+            .chain(fixed_links)
+            .filter_map(|(link, priority)| {
+                let from_stop_id = self.resolver.crs(&link.origin_crs)?;
+                let to_stop_id = self.resolver.crs(&link.dest_crs)?;
                 let time = link.time;
-                Transfer {
+                Some(Transfer {
                     from_stop_id,
                     to_stop_id,
                     transfer_time: time,
-                }
+                    validity: link.validity(),
+                    mode: link.mode,
+                    priority,
+                })
             })
             .into_group_map_by(|t| t.from_stop_id);
 
         Ok(transfers)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 3, day).unwrap()
+    }
+
+    fn timetable(schedules: Vec<Schedule>) -> CifTimetable {
+        CifTimetable {
+            schedules,
+            associations: vec![],
+            stations: vec![],
+            aliases: vec![],
+            links: vec![],
+            fixed_links: vec![],
+            interchanges: vec![],
+        }
+    }
+
+    fn schedule(uid: &str, stp: ScheduleType, from: u32, to: u32) -> Schedule {
+        Schedule::new(uid.to_owned(), date(from), date(to), stp, [true; 7])
+    }
+
+    /// The trips running on each day of March 2025.
+    fn running(timetable: &CifTimetable) -> Vec<Vec<u64>> {
+        let adapter = CifAdapter::new(timetable, &[], &HashMap::new()).unwrap();
+        let calendar = adapter.calendar().unwrap();
+
+        (1..=31)
+            .map(|day| {
+                (0..timetable.schedules.len() as u64)
+                    .filter(|&i| calendar.runs_on(TripId::new(i), date(day)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_one_schedule_per_uid_on_each_date() {
+        let timetable = timetable(vec![
+            schedule("C12345", ScheduleType::Permanent, 1, 31),
+            schedule("C12345", ScheduleType::Overlay, 10, 12),
+            schedule("C12345", ScheduleType::Cancellation, 11, 11),
+            schedule("C12345", ScheduleType::Cancellation, 20, 20),
+            schedule("C12345", ScheduleType::New, 25, 26),
+        ]);
+
+        let running = running(&timetable);
+
+        for (day, trips) in (1..=31).zip(&running) {
+            let expected: &[u64] = match day {
+                11 | 20 => &[],
+                10 | 12 => &[1],
+                25 | 26 => &[4],
+                _ => &[0],
+            };
+            assert_eq!(trips, expected, "trips running on 2025-03-{day:02}");
+        }
+    }
+
+    #[test]
+    fn keeps_schedules_with_different_uids_apart() {
+        let timetable = timetable(vec![
+            schedule("C12345", ScheduleType::Permanent, 1, 31),
+            schedule("C67890", ScheduleType::Overlay, 10, 12),
+            schedule("C67890", ScheduleType::Cancellation, 11, 11),
+        ]);
+
+        let running = running(&timetable);
+
+        assert_eq!(running[9], [0, 1]);
+        assert_eq!(running[10], [0]);
+        assert_eq!(running[0], [0]);
+    }
+}
@@ -1,10 +1,19 @@
 use anyhow::{Context, Result, bail};
 use chrono::{NaiveDate, NaiveTime, TimeDelta};
+use itertools::Itertools;
 use std::{
+    array,
     io::{BufRead, BufReader, Read},
-    str::FromStr,
 };
 
+use crate::csa::{TransferMode, Validity};
+
+const FIRST_MINUTE: NaiveTime = NaiveTime::from_hms_opt(0, 1, 0).expect("valid time");
+const LAST_MINUTE: NaiveTime = NaiveTime::from_hms_opt(23, 59, 0).expect("valid time");
+/// The last instant of the day, for windows that are open until midnight.
+const END_OF_DAY: NaiveTime =
+    NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).expect("valid time");
+
 pub fn parse_alf<R: Read>(reader: R) -> Result<Vec<Link>> {
     let reader = BufReader::new(reader);
     let mut links = vec![];
@@ -17,54 +26,126 @@ pub fn parse_alf<R: Read>(reader: R) -> Result<Vec<Link>> {
     Ok(links)
 }
 
-#[allow(unused)]
 #[derive(Debug, Default)]
 pub struct Link {
-    mode: Mode,
+    pub mode: TransferMode,
     pub origin_crs: String,
     pub dest_crs: String,
     pub time: TimeDelta,
-    start_time: NaiveTime,
-    end_time: NaiveTime,
-    priority: u8,
-    start_date: Option<NaiveDate>,
-    end_date: Option<NaiveDate>,
-    days_of_week: Option<[bool; 7]>,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    /// Which of several overlapping links between the same stations applies, with 1
+    /// taking precedence over 2 and so on.
+    pub priority: u8,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub days_of_week: Option<[bool; 7]>,
 }
 
-#[derive(Debug, Default)]
-pub enum Mode {
-    Bus,
-    Tube,
-    #[default]
-    Walk,
-    Ferry,
-    Metro,
-    Tram,
-    Taxi,
-    Transfer,
-}
+impl Link {
+    /// A link that can be used at any time.
+    pub fn fixed(
+        mode: TransferMode,
+        origin_crs: String,
+        dest_crs: String,
+        time: TimeDelta,
+    ) -> Self {
+        Self {
+            mode,
+            origin_crs,
+            dest_crs,
+            time,
+            end_time: LAST_MINUTE,
+            ..Default::default()
+        }
+    }
+
+    /// When the link can be used, or `None` if it is open all day, every day.
+    pub fn validity(&self) -> Option<Validity> {
+        let all_day = self.is_all_day();
+        let every_day = self.start_date.is_none()
+            && self.end_date.is_none()
+            && self.days_of_week.is_none_or(|days| days.iter().all(|&d| d));
+        let (start_time, end_time) = if all_day {
+            (NaiveTime::MIN, END_OF_DAY)
+        } else {
+            (self.start_time, self.end_time)
+        };
 
-impl FromStr for Mode {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self> {
-        Ok(match s {
-            "BUS" => Mode::Bus,
-            "TUBE" => Mode::Tube,
-            "WALK" => Mode::Walk,
-            "FERRY" => Mode::Ferry,
-            "METRO" => Mode::Metro,
-            "TRAM" => Mode::Tram,
-            "TAXI" => Mode::Taxi,
-            "TRANSFER" => Mode::Transfer,
-            _ => bail!("unexpected value for mode: {s}"),
+        (!(all_day && every_day)).then_some(Validity {
+            start_time,
+            end_time,
+            start_date: self.start_date,
+            end_date: self.end_date,
+            days_run: self.days_of_week,
         })
     }
+
+    /// Whether both links can be used at some time on some day. Links that run past
+    /// midnight are taken to run on the day after each of their days as well, so
+    /// this can find overlaps that aren't there but never misses one.
+    pub fn overlaps(&self, other: &Link) -> bool {
+        let not_after = |start: Option<NaiveDate>, end: Option<NaiveDate>| match (start, end) {
+            (Some(start), Some(end)) => start <= end,
+            _ => true,
+        };
+        let times = self
+            .time_windows()
+            .into_iter()
+            .cartesian_product(other.time_windows())
+            .any(|((a_start, a_end), (b_start, b_end))| a_start <= b_end && b_start <= a_end);
+        let days = match (self.days_used(), other.days_used()) {
+            (Some(a), Some(b)) => a.iter().zip(&b).any(|(&a, &b)| a && b),
+            _ => true,
+        };
+
+        times
+            && not_after(self.start_date, other.last_date())
+            && not_after(other.start_date, self.last_date())
+            && days
+    }
+
+    /// Links open from 0000 or 0001 until 2359 are open all day, rather than closing
+    /// for a minute or two around midnight.
+    fn is_all_day(&self) -> bool {
+        self.start_time <= FIRST_MINUTE && self.end_time >= LAST_MINUTE
+    }
+
+    fn is_overnight(&self) -> bool {
+        !self.is_all_day() && self.start_time > self.end_time
+    }
+
+    /// The times of day the link can be used, split at midnight.
+    fn time_windows(&self) -> Vec<(NaiveTime, NaiveTime)> {
+        if self.is_all_day() {
+            vec![(NaiveTime::MIN, END_OF_DAY)]
+        } else if self.is_overnight() {
+            vec![
+                (self.start_time, END_OF_DAY),
+                (NaiveTime::MIN, self.end_time),
+            ]
+        } else {
+            vec![(self.start_time, self.end_time)]
+        }
+    }
+
+    /// The last date the link can be used on, including after midnight.
+    fn last_date(&self) -> Option<NaiveDate> {
+        let after_midnight = TimeDelta::days(self.is_overnight().into());
+        self.end_date.map(|date| date + after_midnight)
+    }
+
+    /// The days of the week the link can be used on, including after midnight.
+    fn days_used(&self) -> Option<[bool; 7]> {
+        let overnight = self.is_overnight();
+        self.days_of_week
+            .map(|days| array::from_fn(|i| days[i] || (overnight && days[(i + 6) % 7])))
+    }
 }
 
 #[derive(Default, Debug)]
 struct Acc {
-    mode: Option<Mode>,
+    mode: Option<TransferMode>,
     origin: Option<String>,
     destination: Option<String>,
     time: Option<TimeDelta>,
@@ -141,3 +222,85 @@ pub fn parse_link(input: &str) -> Result<Link> {
 
     acc.finish().context("failed to create link")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(fields: &str) -> Link {
+        parse_link(&format!("M=WALK,O=EUS,D=KGX,T=10,P=1,{fields}")).unwrap()
+    }
+
+    fn time(hh_mm: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(hh_mm, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_link() {
+        let link = parse_link(
+            "M=TUBE,O=EUS,D=KGX,T=11,S=0530,E=0030,P=2,F=01/03/2025,U=31/03/2025,R=1111100",
+        )
+        .unwrap();
+
+        assert_eq!(link.mode, TransferMode::Tube);
+        assert_eq!(link.time, TimeDelta::minutes(11));
+        assert_eq!(link.start_time, time("05:30"));
+        assert_eq!(link.end_time, time("00:30"));
+        assert_eq!(link.priority, 2);
+        assert_eq!(link.end_date, NaiveDate::from_ymd_opt(2025, 3, 31));
+        assert_eq!(
+            link.days_of_week,
+            Some([true, true, true, true, true, false, false])
+        );
+    }
+
+    #[test]
+    fn treats_links_from_one_minute_past_to_one_minute_to_midnight_as_all_day() {
+        assert!(link("S=0000,E=2359").validity().is_none());
+        assert!(link("S=0001,E=2359").validity().is_none());
+
+        let validity = link("S=0001,E=2359,R=1111100").validity().unwrap();
+        assert_eq!(validity.start_time, NaiveTime::MIN);
+        assert_eq!(validity.end_time, END_OF_DAY);
+
+        let validity = link("S=0700,E=1900").validity().unwrap();
+        assert_eq!(validity.start_time, time("07:00"));
+        assert_eq!(validity.end_time, time("19:00"));
+    }
+
+    #[test]
+    fn finds_overlapping_times_of_day() {
+        let day = link("S=0700,E=1900");
+
+        assert!(day.overlaps(&link("S=1800,E=2200")));
+        assert!(day.overlaps(&link("S=0001,E=2359")));
+        assert!(!day.overlaps(&link("S=1930,E=2200")));
+    }
+
+    #[test]
+    fn finds_overlaps_with_links_running_past_midnight() {
+        let overnight = link("S=2200,E=0200");
+
+        assert!(overnight.overlaps(&link("S=0100,E=0300")));
+        assert!(overnight.overlaps(&link("S=2300,E=2330")));
+        assert!(overnight.overlaps(&link("S=2330,E=0030")));
+        assert!(!overnight.overlaps(&link("S=0300,E=2100")));
+        assert!(link("S=0530,E=0030").overlaps(&link("S=0001,E=2359")));
+    }
+
+    #[test]
+    fn finds_overlapping_dates_and_days() {
+        let march = link("S=0700,E=1900,F=01/03/2025,U=31/03/2025");
+        assert!(march.overlaps(&link("S=0700,E=1900,F=31/03/2025,U=30/04/2025")));
+        assert!(!march.overlaps(&link("S=0700,E=1900,F=01/04/2025,U=30/04/2025")));
+
+        let weekdays = link("S=0700,E=1900,R=1111100");
+        assert!(weekdays.overlaps(&link("S=0700,E=1900,R=0000110")));
+        assert!(!weekdays.overlaps(&link("S=0700,E=1900,R=0000011")));
+
+        // After midnight on Friday night is Saturday
+        let weekday_nights = link("S=2200,E=0200,R=1111100");
+        assert!(weekday_nights.overlaps(&link("S=0100,E=0300,R=0000010")));
+        assert!(!weekday_nights.overlaps(&link("S=0100,E=0300,R=0000001")));
+    }
+}
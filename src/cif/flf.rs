@@ -0,0 +1,89 @@
+use anyhow::{Context, Result, bail};
+use chrono::TimeDelta;
+use std::io::{BufRead, BufReader, Read};
+
+use super::alf::Link;
+
+/// Parses a fixed link file, whose links can be used at any time.
+pub fn parse_flf<R: Read>(reader: R) -> Result<Vec<Link>> {
+    let reader = BufReader::new(reader);
+    let mut links = vec![];
+    for line in reader.lines() {
+        let input = line?;
+        let input = input.trim();
+        if input.is_empty() || input == "END" {
+            continue;
+        }
+
+        links.push(parse_fixed_link(input)?);
+    }
+
+    Ok(links)
+}
+
+/// Parses a line such as `ADDITIONAL LINK: WALK BETWEEN EUS AND EST IN   5 MINUTES`.
+fn parse_fixed_link(input: &str) -> Result<Link> {
+    let rest = input
+        .strip_prefix("ADDITIONAL LINK:")
+        .with_context(|| format!("unexpected fixed link: {input}"))?;
+
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let [
+        mode,
+        "BETWEEN",
+        origin,
+        "AND",
+        destination,
+        "IN",
+        minutes,
+        "MINUTES",
+    ] = fields[..]
+    else {
+        bail!("unexpected fixed link: {input}");
+    };
+
+    let minutes: i64 = minutes
+        .parse()
+        .with_context(|| format!("invalid time minutes: {minutes}"))?;
+
+    Ok(Link::fixed(
+        mode.parse()?,
+        origin.to_owned(),
+        destination.to_owned(),
+        TimeDelta::minutes(minutes),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::TransferMode;
+
+    #[test]
+    fn parses_fixed_link() {
+        let link =
+            parse_fixed_link("ADDITIONAL LINK: TUBE BETWEEN EUS AND KGX IN  11 MINUTES").unwrap();
+
+        assert_eq!(link.mode, TransferMode::Tube);
+        assert_eq!(link.origin_crs, "EUS");
+        assert_eq!(link.dest_crs, "KGX");
+        assert_eq!(link.time, TimeDelta::minutes(11));
+        assert!(link.validity().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_fixed_link() {
+        assert!(parse_fixed_link("ADDITIONAL LINK: WALK BETWEEN EUS IN 5 MINUTES").is_err());
+        assert!(parse_fixed_link("WALK BETWEEN EUS AND EST IN 5 MINUTES").is_err());
+    }
+
+    #[test]
+    fn skips_blank_lines_and_end_marker() {
+        let flf = "ADDITIONAL LINK: WALK BETWEEN EUS AND EST IN   5 MINUTES\n\nEND\n";
+        let links = parse_flf(flf.as_bytes()).unwrap();
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].mode, TransferMode::Walk);
+        assert_eq!(links[0].time, TimeDelta::minutes(5));
+    }
+}
@@ -1,5 +1,4 @@
 use std::{
-    collections::HashMap,
     io::{BufRead, BufReader, Read},
     str::FromStr,
 };
@@ -7,34 +6,39 @@ use std::{
 use anyhow::Result;
 use chrono::{NaiveDate, NaiveTime};
 
-use crate::cif::{parse_date_yymmdd, parse_hhmm};
+use crate::{
+    cif::{parse_date_yymmdd, parse_hhmm},
+    csa::ServiceKind,
+};
 
-#[allow(unused)]
 pub struct Mca {
     pub schedules: Vec<Schedule>,
-    pub tiploc_to_crs: HashMap<String, String>,
+    pub associations: Vec<Association>,
 }
 
-pub fn parse_mca<R: Read>(reader: R) -> Result<Vec<Schedule>> {
+pub fn parse_mca<R: Read>(reader: R) -> Result<Mca> {
     let reader = BufReader::new(reader);
     let mut schedules = vec![];
+    let mut associations = vec![];
 
     let mut parsing_trip = false;
+    // A CR record applies from the next location the train calls at
+    let mut pending_change = None;
     for line in reader.lines() {
         let line: String = line?;
 
         match &line[0..2] {
+            "AA" => associations.push(Association::from_str(&line)?),
             "BS" => {
+                let transaction = Transaction::from_line(&line)?;
                 let trip_id = line[3..9].to_owned();
                 let start_date = parse_date_yymmdd(&line[9..15])?;
-                let end_date = parse_date_yymmdd(&line[15..21])?;
-                let trip_type = match line.chars().last() {
-                    Some('P') => ScheduleType::Permanent,
-                    Some('O') => ScheduleType::Overlay,
-                    Some('N') => ScheduleType::New,
-                    Some('C') => ScheduleType::Cancellation,
-                    _ => anyhow::bail!("Unexpected character at end of line: {line}"),
+                // Deletions only give the UID, start date and STP indicator
+                let end_date = match transaction {
+                    Transaction::Delete => start_date,
+                    _ => parse_date_yymmdd(&line[15..21])?,
                 };
+                let trip_type = ScheduleType::from_line(&line)?;
 
                 let mut days_run = [false; 7];
                 line[21..28].char_indices().for_each(|(i, d)| {
@@ -42,13 +46,30 @@ pub fn parse_mca<R: Read>(reader: R) -> Result<Vec<Schedule>> {
                 });
 
                 parsing_trip = true;
-                schedules.push(Schedule::new(
-                    trip_id, start_date, end_date, trip_type, days_run,
-                ));
+                pending_change = None;
+                let mut schedule =
+                    Schedule::new(trip_id, start_date, end_date, trip_type, days_run);
+                schedule.transaction = transaction;
+                schedule.status = line[29..30].trim().to_owned();
+                schedule.category = line[30..32].trim().to_owned();
+                schedule.headcode = line[32..36].trim().to_owned();
+                schedule.service_code = line[41..49].trim().to_owned();
+                schedule.power_type = line[50..53].trim().to_owned();
+                schedules.push(schedule);
+            }
+            "BX" if parsing_trip => {
+                if let Some(trip) = schedules.last_mut() {
+                    trip.operator = Some(line[11..13].trim().to_owned()).filter(|o| !o.is_empty());
+                    trip.applicable_timetable = &line[13..14] == "Y";
+                }
             }
+            "CR" if parsing_trip => pending_change = Some(ChangeEnRoute::from_str(&line)?),
             "LI" if !valid_activities(&line[42..54]) => continue,
-            "LO" | "LI" | "LD" if parsing_trip => {
-                let loc = Location::from_str(&line)?;
+            "LO" | "LI" | "LT" if parsing_trip => {
+                let mut loc = Location::from_str(&line)?;
+                if let Location::Intermediate { change, .. } = &mut loc {
+                    *change = pending_change.take();
+                }
 
                 if loc.is_dest() {
                     parsing_trip = false;
@@ -62,29 +83,183 @@ pub fn parse_mca<R: Read>(reader: R) -> Result<Vec<Schedule>> {
         }
     }
 
-    Ok(schedules)
+    Ok(Mca {
+        schedules,
+        associations,
+    })
 }
 
 fn valid_activities(s: &str) -> bool {
-    s.contains("T ") || s.contains("D ") || s.contains("U ")
+    activity_codes(s)
+        .iter()
+        .any(|a| matches!(a.as_str(), "T" | "D" | "U"))
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+/// A platform field, or `None` if it is blank.
+fn platform(s: &str) -> Option<String> {
+    Some(s.trim().to_owned()).filter(|p| !p.is_empty())
+}
+
+/// Splits an activity field into its two-character codes, dropping blanks.
+fn activity_codes(s: &str) -> Vec<String> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|c| String::from_utf8_lossy(c).trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+/// Short-term planning indicator, in increasing order of precedence: on any date a
+/// train runs to the highest ranked schedule with its UID that applies.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum ScheduleType {
     Permanent,
-    New,
     Overlay,
+    New,
     Cancellation,
 }
 
+/// Transaction type of a BS or AA record. Full extracts only contain new records,
+/// while update extracts also delete or revise records from earlier extracts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transaction {
+    New,
+    Delete,
+    Revise,
+}
+
+impl Transaction {
+    fn from_line(line: &str) -> Result<Self> {
+        Ok(match &line[2..3] {
+            "N" => Transaction::New,
+            "D" => Transaction::Delete,
+            "R" => Transaction::Revise,
+            other => anyhow::bail!("Unexpected transaction type {other:?} in {line}"),
+        })
+    }
+}
+
+impl ScheduleType {
+    /// Reads the STP indicator at the end of a BS or AA record.
+    fn from_line(line: &str) -> Result<Self> {
+        Ok(match line.chars().last() {
+            Some('P') => ScheduleType::Permanent,
+            Some('O') => ScheduleType::Overlay,
+            Some('N') => ScheduleType::New,
+            Some('C') => ScheduleType::Cancellation,
+            _ => anyhow::bail!("Unexpected character at end of line: {line}"),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AssociationCategory {
+    /// The associated train joins the base train.
+    Join,
+    /// The associated train divides from the base train.
+    Divide,
+    /// The associated train is formed from the base train after it terminates.
+    Next,
+}
+
+/// An AA record linking two trains that join or divide at a location.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct Association {
+    pub transaction: Transaction,
+    pub base_uid: String,
+    pub assoc_uid: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    /// Days of the week on which the base train's association applies.
+    pub days_run: [bool; 7],
+    pub category: AssociationCategory,
+    /// Days from the base train's service day to the associated train's: 1 when the
+    /// association happens after midnight on the associated train only, -1 for the
+    /// reverse.
+    pub day_offset: i64,
+    pub tiploc: String,
+    /// Passenger associations, as opposed to purely operational ones.
+    pub passenger: bool,
+    pub stp: ScheduleType,
+}
+
+impl FromStr for Association {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let transaction = Transaction::from_line(s)?;
+        let base_uid = s[3..9].to_owned();
+        let assoc_uid = s[9..15].to_owned();
+        let start_date = parse_date_yymmdd(&s[15..21])?;
+        // Deletions only give the UIDs, start date, location and STP indicator
+        let end_date = match transaction {
+            Transaction::Delete => start_date,
+            _ => parse_date_yymmdd(&s[21..27])?,
+        };
+
+        let mut days_run = [false; 7];
+        s[27..34].char_indices().for_each(|(i, d)| {
+            days_run[i] = d == '1';
+        });
+
+        let category = match &s[34..36] {
+            "JJ" => AssociationCategory::Join,
+            "VV" => AssociationCategory::Divide,
+            "NP" => AssociationCategory::Next,
+            _ if transaction == Transaction::Delete => AssociationCategory::Next,
+            other => anyhow::bail!("Unexpected association category {other:?} in {s}"),
+        };
+        let day_offset = match &s[36..37] {
+            "N" => 1,
+            "P" => -1,
+            _ => 0,
+        };
+        let tiploc = s[37..44].trim().to_string();
+        let passenger = &s[47..48] == "P";
+        let stp = ScheduleType::from_line(s)?;
+
+        Ok(Association {
+            transaction,
+            base_uid,
+            assoc_uid,
+            start_date,
+            end_date,
+            days_run,
+            category,
+            day_offset,
+            tiploc,
+            passenger,
+            stp,
+        })
+    }
+}
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct Schedule {
+    pub transaction: Transaction,
     pub id: String,
     pub start_date: NaiveDate,
     pub end_date: NaiveDate,
     pub trip_type: ScheduleType,
     pub days_run: [bool; 7],
+    /// Train status, such as "P" for passenger and parcels or "B" for bus, with STP
+    /// schedules using "1" to "5".
+    pub status: String,
+    /// Train category, such as "OO" for ordinary passenger, "XX" for express
+    /// passenger, "BR" for a replacement bus or "SS" for a ship.
+    pub category: String,
+    /// Power type, such as "EMU", "DMU" or "E" for an electric locomotive.
+    pub power_type: String,
+    /// Signalling ID, such as "1A23".
+    pub headcode: String,
+    /// Code identifying the service for revenue purposes.
+    pub service_code: String,
+    /// ATOC code of the train operator, from the BX record.
+    pub operator: Option<String>,
+    /// Whether the train is subject to performance monitoring against the timetable.
+    pub applicable_timetable: bool,
     pub locations: Vec<Location>,
 }
 
@@ -97,11 +272,19 @@ impl Schedule {
         days_run: [bool; 7],
     ) -> Self {
         Self {
+            transaction: Transaction::New,
             id,
             start_date,
             end_date,
             trip_type,
             days_run,
+            status: String::new(),
+            category: String::new(),
+            power_type: String::new(),
+            headcode: String::new(),
+            service_code: String::new(),
+            operator: None,
+            applicable_timetable: false,
             locations: vec![],
         }
     }
@@ -109,6 +292,47 @@ impl Schedule {
     pub fn add_location(&mut self, loc: Location) {
         self.locations.push(loc);
     }
+
+    /// What the schedule runs as at its origin, judging by its category and status.
+    pub fn service_kind(&self) -> ServiceKind {
+        service_kind(&self.category, &self.status)
+    }
+}
+
+/// What a train of the given category and status runs as.
+pub fn service_kind(category: &str, status: &str) -> ServiceKind {
+    match (category, status) {
+        ("BR", _) => ServiceKind::ReplacementBus,
+        ("BS", _) | (_, "B" | "5") => ServiceKind::Bus,
+        ("SS", _) | (_, "S" | "4") => ServiceKind::Ship,
+        _ => ServiceKind::Train,
+    }
+}
+
+/// A CR record, changing a train's details from the location it precedes onwards.
+/// These carry no ATOC code, so the operator stays as in the BX record.
+#[allow(unused)]
+#[derive(Debug, Clone)]
+pub struct ChangeEnRoute {
+    pub tiploc: String,
+    pub category: String,
+    pub headcode: String,
+    pub service_code: String,
+    pub power_type: String,
+}
+
+impl FromStr for ChangeEnRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(ChangeEnRoute {
+            tiploc: s[2..9].trim().to_string(),
+            category: s[10..12].trim().to_string(),
+            headcode: s[12..16].trim().to_string(),
+            service_code: s[21..29].trim().to_string(),
+            power_type: s[30..33].trim().to_string(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -116,15 +340,23 @@ pub enum Location {
     Origin {
         tiploc: String,
         departure_time: NaiveTime,
+        activities: Vec<String>,
+        platform: Option<String>,
     },
     Intermediate {
         tiploc: String,
         arrival_time: NaiveTime,
         departure_time: NaiveTime,
+        activities: Vec<String>,
+        platform: Option<String>,
+        /// Details that change on departure from here.
+        change: Option<ChangeEnRoute>,
     },
     Destination {
         tiploc: String,
         arrival_time: NaiveTime,
+        activities: Vec<String>,
+        platform: Option<String>,
     },
 }
 
@@ -136,20 +368,21 @@ impl FromStr for Location {
             s if s.starts_with("LO") => {
                 let tiploc = s[2..9].trim().to_string();
                 let departure_time = parse_hhmm(&s[15..19])?;
+                let activities = activity_codes(&s[29..41]);
+                let platform = platform(&s[19..22]);
 
                 Ok(Location::Origin {
                     tiploc,
                     departure_time,
+                    activities,
+                    platform,
                 })
             }
             s if s.starts_with("LI") => {
-                let activities = &s[42..54];
-                if !(activities.contains("T ")
-                    || activities.contains("D ")
-                    || activities.contains("U "))
-                {
+                if !valid_activities(&s[42..54]) {
                     anyhow::bail!("Location does not pick up passengers")
                 }
+                let activities = activity_codes(&s[42..54]);
 
                 let tiploc = s[2..9].trim().to_string();
 
@@ -170,15 +403,22 @@ impl FromStr for Location {
                     tiploc,
                     arrival_time,
                     departure_time,
+                    activities,
+                    platform: platform(&s[33..36]),
+                    change: None,
                 })
             }
             s if s.starts_with("LT") => {
                 let tiploc = s[2..9].trim().to_string();
                 let arrival_time = parse_hhmm(&s[15..19])?;
+                let activities = activity_codes(&s[25..37]);
+                let platform = platform(&s[19..22]);
 
                 Ok(Location::Destination {
                     tiploc,
                     arrival_time,
+                    activities,
+                    platform,
                 })
             }
             _ => anyhow::bail!("Invalid location record"),
@@ -211,6 +451,45 @@ impl Location {
         }
     }
 
+    pub fn activities(&self) -> &[String] {
+        match self {
+            Location::Origin { activities, .. } => activities,
+            Location::Intermediate { activities, .. } => activities,
+            Location::Destination { activities, .. } => activities,
+        }
+    }
+
+    pub fn platform(&self) -> Option<&str> {
+        match self {
+            Location::Origin { platform, .. } => platform.as_deref(),
+            Location::Intermediate { platform, .. } => platform.as_deref(),
+            Location::Destination { platform, .. } => platform.as_deref(),
+        }
+    }
+
+    pub fn change(&self) -> Option<&ChangeEnRoute> {
+        match self {
+            Location::Intermediate { change, .. } => change.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether passengers may board here: a stop for picking up ("T" or "U"), or the
+    /// start of the train ("TB").
+    pub fn picks_up(&self) -> bool {
+        self.activities()
+            .iter()
+            .any(|a| matches!(a.as_str(), "T" | "U" | "TB"))
+    }
+
+    /// Whether passengers may alight here: a stop for setting down ("T" or "D"), or
+    /// the end of the train ("TF").
+    pub fn sets_down(&self) -> bool {
+        self.activities()
+            .iter()
+            .any(|a| matches!(a.as_str(), "T" | "D" | "TF"))
+    }
+
     pub fn is_dest(&self) -> bool {
         match self {
             Location::Origin { .. } => false,
@@ -228,3 +507,89 @@ impl Location {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// Joins records into a file, padding each to the 80 characters of a CIF record.
+    fn records(lines: &[&str]) -> String {
+        lines.iter().map(|l| format!("{l:<80}\n")).collect()
+    }
+
+    #[test]
+    fn parses_association() {
+        let line =
+            "AANC12345C678902312102405171111100VVSBRSTLTM  TP                               P";
+        let assoc = Association::from_str(line).unwrap();
+
+        assert_eq!(assoc.transaction, Transaction::New);
+        assert_eq!(assoc.base_uid, "C12345");
+        assert_eq!(assoc.assoc_uid, "C67890");
+        assert_eq!(assoc.start_date, date(2023, 12, 10));
+        assert_eq!(assoc.end_date, date(2024, 5, 17));
+        assert_eq!(assoc.days_run, [true, true, true, true, true, false, false]);
+        assert_eq!(assoc.category, AssociationCategory::Divide);
+        assert_eq!(assoc.day_offset, 0);
+        assert_eq!(assoc.tiploc, "BRSTLTM");
+        assert!(assoc.passenger);
+        assert_eq!(assoc.stp, ScheduleType::Permanent);
+    }
+
+    #[test]
+    fn parses_association_deletion() {
+        let line =
+            "AADC12345C67890231210                BRSTLTM                                   O";
+        let assoc = Association::from_str(line).unwrap();
+
+        assert_eq!(assoc.transaction, Transaction::Delete);
+        assert_eq!(assoc.end_date, assoc.start_date);
+        assert_eq!(assoc.category, AssociationCategory::Next);
+        assert_eq!(assoc.stp, ScheduleType::Overlay);
+    }
+
+    #[test]
+    fn parses_change_en_route() {
+        let change = ChangeEnRoute::from_str("CRFLTN    OO2B45    125474001 EMU150 100").unwrap();
+
+        assert_eq!(change.tiploc, "FLTN");
+        assert_eq!(change.category, "OO");
+        assert_eq!(change.headcode, "2B45");
+        assert_eq!(change.service_code, "25474001");
+        assert_eq!(change.power_type, "EMU");
+    }
+
+    #[test]
+    fn applies_change_en_route_to_the_next_location() {
+        let mca = records(&[
+            "BSNC123452312102405171111100 POO2B45    125473001 DMU150 075      S            P",
+            "BX         GWY",
+            "LOBRSTLTM 0815H08153         TB",
+            "CRFLTN    OO2B45    125474001 EMU150 100",
+            "LIFLTN    0830 0831      083008311        T",
+            "LTSEVTNB  0850 08502     TF",
+        ]);
+        let Mca { schedules, .. } = parse_mca(mca.as_bytes()).unwrap();
+
+        let [schedule] = &schedules[..] else {
+            panic!("expected one schedule, got {}", schedules.len());
+        };
+        assert_eq!(schedule.service_code, "25473001");
+        assert_eq!(schedule.power_type, "DMU");
+        assert_eq!(schedule.operator.as_deref(), Some("GW"));
+
+        let [origin, calling, dest] = &schedule.locations[..] else {
+            panic!("expected three locations, got {}", schedule.locations.len());
+        };
+        assert!(origin.change().is_none());
+        assert_eq!(origin.platform(), Some("3"));
+        let change = calling.change().expect("change at FLTN");
+        assert_eq!(change.service_code, "25474001");
+        assert_eq!(change.power_type, "EMU");
+        assert!(dest.change().is_none());
+    }
+}
@@ -1,18 +1,30 @@
 use anyhow::{Context, Result};
 use chrono::{NaiveDate, NaiveTime};
-use std::{fs::File, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::Path,
+};
 use zip::ZipArchive;
 
 mod adapter;
 mod alf;
+mod flf;
 mod mca;
 mod msn;
+mod osgb;
+mod resolver;
+mod tsi;
 
 use alf::{Link, parse_alf};
-use mca::{Schedule, parse_mca};
-use msn::{Msn, Station};
+use flf::parse_flf;
+use mca::{Association, Mca, Schedule, Transaction, parse_mca};
+use msn::{Alias, Msn, Station};
+use tsi::{Interchange, parse_tsi};
 
-use crate::{cif::adapter::CifAdapter, csa::TransportNetwork};
+pub use adapter::{CifAdapter, StationInfo, read_station_overrides, read_tiploc_mappings};
+
+use crate::csa::TransportNetwork;
 
 pub fn parse_hhmm(s: &str) -> Result<NaiveTime> {
     NaiveTime::parse_from_str(s, "%H%M").with_context(|| format!("bad time (HHMM): {s}"))
@@ -28,8 +40,12 @@ pub fn parse_date_yymmdd(s: &str) -> Result<NaiveDate> {
 
 pub struct CifTimetable {
     pub schedules: Vec<Schedule>,
+    pub associations: Vec<Association>,
     pub stations: Vec<Station>,
+    pub aliases: Vec<Alias>,
     pub links: Vec<Link>,
+    pub fixed_links: Vec<Link>,
+    pub interchanges: Vec<Interchange>,
 }
 
 impl CifTimetable {
@@ -38,8 +54,10 @@ impl CifTimetable {
         let mut archive = ZipArchive::new(file)?;
 
         let mut msn: Option<Result<Msn>> = None;
-        let mut schedules: Option<Result<Vec<Schedule>>> = None;
+        let mut mca: Option<Result<Mca>> = None;
         let mut links: Option<Result<Vec<Link>>> = None;
+        let mut fixed_links: Option<Result<Vec<Link>>> = None;
+        let mut interchanges: Option<Result<Vec<Interchange>>> = None;
 
         for i in 0..archive.len() {
             let file = archive.by_index(i)?;
@@ -48,29 +66,289 @@ impl CifTimetable {
             if name.ends_with(".msn") {
                 msn = Some(Msn::from_reader(file));
             } else if name.ends_with(".mca") {
-                schedules = Some(parse_mca(file));
+                mca = Some(parse_mca(file));
             } else if name.ends_with(".alf") {
                 links = Some(parse_alf(file));
+            } else if name.ends_with(".flf") {
+                fixed_links = Some(parse_flf(file));
+            } else if name.ends_with(".tsi") {
+                interchanges = Some(parse_tsi(file));
             }
         }
 
         let msn = msn.transpose()?.context("Missing MSN file")?;
-        let schedules = schedules.transpose()?.context("Missing MCA file")?;
+        let mca = mca.transpose()?.context("Missing MCA file")?;
 
         let alf = links.transpose()?.context("Missing ALF file")?;
+        let fixed_links = fixed_links.transpose()?.unwrap_or_default();
+        let interchanges = interchanges.transpose()?.unwrap_or_default();
 
         Ok(Self {
-            schedules,
+            schedules: mca.schedules,
+            associations: mca.associations,
             stations: msn.stations,
+            aliases: msn.aliases,
             links: alf,
+            fixed_links,
+            interchanges,
         })
     }
+
+    /// Applies an update extract, either zipped or as a bare MCA file. Deletions and
+    /// revisions replace the schedules and associations with the same UIDs, start
+    /// date and STP indicator.
+    pub fn apply_update<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let is_zip = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+
+        let update = if is_zip {
+            let mut archive = ZipArchive::new(File::open(path)?)?;
+            let mut mca = None;
+            for i in 0..archive.len() {
+                let file = archive.by_index(i)?;
+                let name = file.name().to_ascii_lowercase();
+                if name.ends_with(".mca") || name.ends_with(".cif") {
+                    mca = Some(parse_mca(file));
+                }
+            }
+            mca.transpose()?.context("Missing MCA file")?
+        } else {
+            let file = File::open(path).with_context(|| format!("opening {path:?}"))?;
+            parse_mca(file)?
+        };
+
+        let schedule_key = |s: &Schedule| (s.id.clone(), s.start_date, s.trip_type);
+        let replaced: HashSet<_> = update
+            .schedules
+            .iter()
+            .filter(|s| s.transaction != Transaction::New)
+            .map(schedule_key)
+            .collect();
+        self.schedules
+            .retain(|s| !replaced.contains(&schedule_key(s)));
+        self.schedules.extend(
+            update
+                .schedules
+                .into_iter()
+                .filter(|s| s.transaction != Transaction::Delete),
+        );
+
+        let association_key = |a: &Association| {
+            (
+                a.base_uid.clone(),
+                a.assoc_uid.clone(),
+                a.start_date,
+                a.tiploc.clone(),
+                a.stp,
+            )
+        };
+        let replaced: HashSet<_> = update
+            .associations
+            .iter()
+            .filter(|a| a.transaction != Transaction::New)
+            .map(association_key)
+            .collect();
+        self.associations
+            .retain(|a| !replaced.contains(&association_key(a)));
+        self.associations.extend(
+            update
+                .associations
+                .into_iter()
+                .filter(|a| a.transaction != Transaction::Delete),
+        );
+
+        Ok(())
+    }
 }
 
-impl<'a> TryFrom<&'a CifTimetable> for TransportNetwork {
-    type Error = anyhow::Error;
-    fn try_from(value: &'a CifTimetable) -> Result<Self, Self::Error> {
-        let adapter = CifAdapter::new(value)?;
+impl TransportNetwork {
+    /// Builds a network from a CIF timetable, using `station_overrides` in place of the
+    /// MSN's own station locations where given and `tiploc_mappings` for TIPLOCs the
+    /// MSN doesn't have.
+    pub fn from_cif(
+        timetable: &CifTimetable,
+        station_overrides: &[StationInfo],
+        tiploc_mappings: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let adapter = CifAdapter::new(timetable, station_overrides, tiploc_mappings)?;
         TransportNetwork::from_adapter(&adapter)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+    use mca::ScheduleType;
+
+    /// A BS record running on weekdays, or one deleting a schedule if `end` is blank.
+    fn bs(transaction: char, uid: &str, start: &str, end: &str, stp: char) -> String {
+        let days = if end.trim().is_empty() {
+            "       "
+        } else {
+            "1111100"
+        };
+        format!(
+            "BS{transaction}{uid}{start}{end:<6}{days} POO2B45    125473001 DMU150 075      S            {stp}"
+        )
+    }
+
+    /// An AA record for trains dividing at Bristol Temple Meads, or one deleting such
+    /// an association if `end` is blank.
+    fn aa(transaction: char, base: &str, assoc: &str, start: &str, end: &str) -> String {
+        let blank = "";
+        format!("AA{transaction}{base}{assoc}{start}{end:<6}1111100VVSBRSTLTM  TP{blank:31}P")
+    }
+
+    fn timetable(mca: &[String]) -> CifTimetable {
+        let mca = parse_mca(mca.join("\n").as_bytes()).unwrap();
+        CifTimetable {
+            schedules: mca.schedules,
+            associations: mca.associations,
+            stations: vec![],
+            aliases: vec![],
+            links: vec![],
+            fixed_links: vec![],
+            interchanges: vec![],
+        }
+    }
+
+    fn base_timetable() -> CifTimetable {
+        timetable(&[
+            bs('N', "C12345", "250301", "250531", 'P'),
+            bs('N', "C12345", "250310", "250312", 'O'),
+            bs('N', "C67890", "250301", "250531", 'P'),
+            aa('N', "C12345", "C67890", "250301", "250531"),
+        ])
+    }
+
+    fn update() -> String {
+        [
+            bs('D', "C12345", "250301", "", 'P'),
+            bs('R', "C67890", "250301", "250430", 'P'),
+            bs('N', "C24680", "250401", "250430", 'P'),
+            aa('D', "C12345", "C67890", "250301", ""),
+            aa('N', "C24680", "C67890", "250401", "250430"),
+        ]
+        .join("\n")
+    }
+
+    /// Each schedule's UID, start date, end date and STP indicator, sorted.
+    fn schedules(timetable: &CifTimetable) -> Vec<(String, String, String, ScheduleType)> {
+        let mut schedules: Vec<_> = timetable
+            .schedules
+            .iter()
+            .map(|s| {
+                (
+                    s.id.clone(),
+                    s.start_date.format("%y%m%d").to_string(),
+                    s.end_date.format("%y%m%d").to_string(),
+                    s.trip_type,
+                )
+            })
+            .collect();
+        schedules.sort_unstable();
+        schedules
+    }
+
+    fn assert_updated(timetable: &CifTimetable) {
+        let schedule = |uid: &str, start: &str, end: &str, stp| {
+            (uid.to_owned(), start.to_owned(), end.to_owned(), stp)
+        };
+        assert_eq!(
+            schedules(timetable),
+            [
+                // The overlay isn't deleted along with the permanent schedule
+                schedule("C12345", "250310", "250312", ScheduleType::Overlay),
+                schedule("C24680", "250401", "250430", ScheduleType::Permanent),
+                schedule("C67890", "250301", "250430", ScheduleType::Permanent),
+            ]
+        );
+
+        let [association] = &timetable.associations[..] else {
+            panic!(
+                "expected one association, got {}",
+                timetable.associations.len()
+            );
+        };
+        assert_eq!(association.base_uid, "C24680");
+        assert_eq!(association.assoc_uid, "C67890");
+    }
+
+    /// A path in the temporary directory unique to this test run.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "{}-{}-{name}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn applies_bare_update_file() {
+        let path = temp_path("update.mca");
+        std::fs::write(&path, update()).unwrap();
+
+        let mut timetable = base_timetable();
+        let result = timetable.apply_update(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        assert_updated(&timetable);
+    }
+
+    #[test]
+    fn applies_zipped_update() {
+        let path = temp_path("update.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("RJTTC123.MCA", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(update().as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let mut timetable = base_timetable();
+        let result = timetable.apply_update(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        assert_updated(&timetable);
+    }
+
+    #[test]
+    fn only_replaces_schedules_with_the_same_start_date_and_stp_indicator() {
+        let path = temp_path("revision.mca");
+        std::fs::write(
+            &path,
+            [
+                bs('R', "C12345", "250310", "250311", 'O'),
+                bs('D', "C67890", "250302", "", 'P'),
+                bs('D', "C67890", "250301", "", 'O'),
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let mut timetable = base_timetable();
+        let result = timetable.apply_update(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        let schedule = |uid: &str, start: &str, end: &str, stp| {
+            (uid.to_owned(), start.to_owned(), end.to_owned(), stp)
+        };
+        assert_eq!(
+            schedules(&timetable),
+            [
+                schedule("C12345", "250301", "250531", ScheduleType::Permanent),
+                schedule("C12345", "250310", "250311", ScheduleType::Overlay),
+                schedule("C67890", "250301", "250531", ScheduleType::Permanent),
+            ]
+        );
+        assert_eq!(timetable.associations.len(), 1);
+    }
+}
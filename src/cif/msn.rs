@@ -33,7 +33,7 @@ impl Msn {
 fn parse_msn<R: BufRead>(reader: R) -> Result<Msn> {
     let mut header = None;
     let mut stations = Vec::new();
-    let aliases = Vec::new();
+    let mut aliases = Vec::new();
 
     let mut parsed_header = false;
     for line in reader.lines() {
@@ -48,7 +48,7 @@ fn parse_msn<R: BufRead>(reader: R) -> Result<Msn> {
         } else if line.starts_with('A') {
             stations.push(Station::from_str(&line)?);
         } else if line.starts_with('L') {
-            // aliases.push(Alias::from_str(&line)?);
+            aliases.push(Alias::from_str(&line)?);
         }
     }
 
@@ -101,7 +101,9 @@ pub struct Station {
     pub tiploc: String,
     pub minor_crs: String,
     pub crs: String,
+    /// National Grid easting in metres.
     pub easting: u32,
+    /// National Grid northing in metres.
     pub northing: u32,
     pub min_change_time: TimeDelta,
 }
@@ -119,13 +121,17 @@ impl FromStr for Station {
         let tiploc = s[36..43].trim().to_string();
         let minor_crs = s[43..46].to_string();
         let crs = s[49..52].to_string();
-        let easting: u32 = s[55..57]
+        // Grid references are in units of 100m, offset by 10000 for eastings and
+        // 60000 for northings
+        let easting: u32 = s[52..57]
             .parse()
             .with_context(|| format!("Invalid easting in {s}"))?;
+        let easting = easting.saturating_sub(10_000) * 100;
 
         let northing: u32 = s[58..63]
             .parse()
             .with_context(|| format!("Invalid northing in {s}"))?;
+        let northing = northing.saturating_sub(60_000) * 100;
 
         let min_change_time: i64 = s[63..65]
             .trim()
@@ -145,7 +151,9 @@ impl FromStr for Station {
     }
 }
 
-#[allow(unused)]
+/// Another name for the station called `station_name`, such as "KINGS X" for
+/// "LONDON KINGS CROSS".
+#[derive(Debug)]
 pub struct Alias {
     pub station_name: String,
     pub station_alias: String,
@@ -155,8 +163,17 @@ impl FromStr for Alias {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let station_name = s[5..31].to_string();
-        let station_alias = s[36..61].to_string();
+        let station_name = s
+            .get(5..31)
+            .with_context(|| format!("Missing station name in {s}"))?
+            .trim()
+            .to_string();
+        // Trailing spaces may be stripped from the alias
+        let station_alias = s
+            .get(36..s.len().min(62))
+            .with_context(|| format!("Missing alias in {s}"))?
+            .trim()
+            .to_string();
 
         Ok(Alias {
             station_name,
@@ -164,3 +181,28 @@ impl FromStr for Alias {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_alias_filling_its_field() {
+        let line = format!(
+            "L    {:<26}     {:<26}",
+            "HEATHROW TERMINAL 5", "HEATHROW AIRPORT TERMINAL5"
+        );
+        let alias = Alias::from_str(&line).unwrap();
+
+        assert_eq!(alias.station_name, "HEATHROW TERMINAL 5");
+        assert_eq!(alias.station_alias, "HEATHROW AIRPORT TERMINAL5");
+    }
+
+    #[test]
+    fn parses_alias_with_trailing_spaces_stripped() {
+        let alias = Alias::from_str("L    ABBEY WOOD (LONDON)            ABBEY WOOD").unwrap();
+
+        assert_eq!(alias.station_name, "ABBEY WOOD (LONDON)");
+        assert_eq!(alias.station_alias, "ABBEY WOOD");
+    }
+}
@@ -0,0 +1,156 @@
+//! Conversion from Ordnance Survey National Grid references to WGS84, following the
+//! method in "A Guide to Coordinate Systems in Great Britain". The Helmert step is
+//! accurate to a few metres, which is plenty for locating stations.
+
+/// Airy 1830 ellipsoid, used by OSGB36.
+const AIRY_A: f64 = 6_377_563.396;
+const AIRY_B: f64 = 6_356_256.909;
+
+/// GRS80 ellipsoid, used by WGS84.
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_B: f64 = 6_356_752.314_245;
+
+/// National Grid true origin and scale factor on the central meridian.
+const F0: f64 = 0.999_601_271_7;
+const LAT0_DEG: f64 = 49.0;
+const LON0_DEG: f64 = -2.0;
+const E0: f64 = 400_000.0;
+const N0: f64 = -100_000.0;
+
+/// Helmert parameters from OSGB36 to WGS84: translations in metres, scale in ppm and
+/// rotations in arc seconds.
+const TX: f64 = 446.448;
+const TY: f64 = -125.157;
+const TZ: f64 = 542.060;
+const S_PPM: f64 = -20.4894;
+const RX_SEC: f64 = 0.1502;
+const RY_SEC: f64 = 0.2470;
+const RZ_SEC: f64 = 0.8421;
+
+/// Converts a National Grid easting and northing in metres to WGS84 latitude and
+/// longitude in degrees.
+pub fn osgb36_to_wgs84(easting: f64, northing: f64) -> (f64, f64) {
+    let (lat, lon) = grid_to_osgb36(easting, northing);
+    let (x, y, z) = to_cartesian(lat, lon, AIRY_A, AIRY_B);
+    let (x, y, z) = helmert(x, y, z);
+    let (lat, lon) = from_cartesian(x, y, z, WGS84_A, WGS84_B);
+
+    (lat.to_degrees(), lon.to_degrees())
+}
+
+/// Inverse transverse Mercator projection onto the Airy ellipsoid, in radians.
+fn grid_to_osgb36(easting: f64, northing: f64) -> (f64, f64) {
+    let (a, b) = (AIRY_A, AIRY_B);
+    let e2 = 1.0 - (b * b) / (a * a);
+    let n = (a - b) / (a + b);
+    let lat0 = LAT0_DEG.to_radians();
+    let lon0 = LON0_DEG.to_radians();
+
+    let meridional_arc = |lat: f64| {
+        let (n2, n3) = (n * n, n * n * n);
+        let (dlat, slat) = (lat - lat0, lat + lat0);
+        b * F0
+            * ((1.0 + n + 1.25 * n2 + 1.25 * n3) * dlat
+                - (3.0 * n + 3.0 * n2 + 2.625 * n3) * dlat.sin() * slat.cos()
+                + (1.875 * n2 + 1.875 * n3) * (2.0 * dlat).sin() * (2.0 * slat).cos()
+                - (35.0 / 24.0) * n3 * (3.0 * dlat).sin() * (3.0 * slat).cos())
+    };
+
+    let mut lat = lat0;
+    let mut m = 0.0;
+    while (northing - N0 - m).abs() >= 1e-5 {
+        lat += (northing - N0 - m) / (a * F0);
+        m = meridional_arc(lat);
+    }
+
+    let (sin_lat, cos_lat, tan_lat) = (lat.sin(), lat.cos(), lat.tan());
+    let nu = a * F0 / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+    let rho = a * F0 * (1.0 - e2) / (1.0 - e2 * sin_lat * sin_lat).powf(1.5);
+    let eta2 = nu / rho - 1.0;
+
+    let (t2, t4, t6) = (tan_lat.powi(2), tan_lat.powi(4), tan_lat.powi(6));
+    let sec_lat = 1.0 / cos_lat;
+
+    let vii = tan_lat / (2.0 * rho * nu);
+    let viii = tan_lat / (24.0 * rho * nu.powi(3)) * (5.0 + 3.0 * t2 + eta2 - 9.0 * t2 * eta2);
+    let ix = tan_lat / (720.0 * rho * nu.powi(5)) * (61.0 + 90.0 * t2 + 45.0 * t4);
+    let x = sec_lat / nu;
+    let xi = sec_lat / (6.0 * nu.powi(3)) * (nu / rho + 2.0 * t2);
+    let xii = sec_lat / (120.0 * nu.powi(5)) * (5.0 + 28.0 * t2 + 24.0 * t4);
+    let xiia = sec_lat / (5040.0 * nu.powi(7)) * (61.0 + 662.0 * t2 + 1320.0 * t4 + 720.0 * t6);
+
+    let de = easting - E0;
+    let lat = lat - vii * de.powi(2) + viii * de.powi(4) - ix * de.powi(6);
+    let lon = lon0 + x * de - xi * de.powi(3) + xii * de.powi(5) - xiia * de.powi(7);
+
+    (lat, lon)
+}
+
+fn to_cartesian(lat: f64, lon: f64, a: f64, b: f64) -> (f64, f64, f64) {
+    let e2 = 1.0 - (b * b) / (a * a);
+    let nu = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+
+    (
+        nu * lat.cos() * lon.cos(),
+        nu * lat.cos() * lon.sin(),
+        (1.0 - e2) * nu * lat.sin(),
+    )
+}
+
+fn helmert(x: f64, y: f64, z: f64) -> (f64, f64, f64) {
+    let s = 1.0 + S_PPM * 1e-6;
+    let arcsec = |v: f64| (v / 3600.0).to_radians();
+    let (rx, ry, rz) = (arcsec(RX_SEC), arcsec(RY_SEC), arcsec(RZ_SEC));
+
+    (
+        TX + s * x - rz * y + ry * z,
+        TY + rz * x + s * y - rx * z,
+        TZ - ry * x + rx * y + s * z,
+    )
+}
+
+fn from_cartesian(x: f64, y: f64, z: f64, a: f64, b: f64) -> (f64, f64) {
+    let e2 = 1.0 - (b * b) / (a * a);
+    let p = x.hypot(y);
+
+    let mut lat = z.atan2(p * (1.0 - e2));
+    loop {
+        let nu = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        let next = (z + e2 * nu * lat.sin()).atan2(p);
+        let converged = (next - lat).abs() < 1e-12;
+        lat = next;
+        if converged {
+            break;
+        }
+    }
+
+    (lat, y.atan2(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Caister water tower, the worked example in "A Guide to Coordinate Systems in
+    /// Great Britain".
+    const CAISTER: (f64, f64) = (651_409.903, 313_177.270);
+
+    #[test]
+    fn projects_grid_reference_onto_airy_ellipsoid() {
+        let (lat, lon) = grid_to_osgb36(CAISTER.0, CAISTER.1);
+
+        // 52°39'27.2531"N, 1°43'4.5177"E
+        assert!((lat.to_degrees() - 52.657_570_3).abs() < 1e-7);
+        assert!((lon.to_degrees() - 1.717_921_6).abs() < 1e-7);
+    }
+
+    #[test]
+    fn converts_grid_reference_to_wgs84() {
+        let (lat, lon) = osgb36_to_wgs84(CAISTER.0, CAISTER.1);
+
+        // Moving from OSGB36 to WGS84 shifts points in East Anglia about 45 m north
+        // and 125 m west
+        assert!((lat - 52.657_978).abs() < 5e-5, "latitude {lat}");
+        assert!((lon - 1.716_053).abs() < 5e-5, "longitude {lon}");
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use tracing::warn;
+
+use crate::{
+    cif::{
+        StationInfo,
+        msn::{Alias, Station},
+        osgb::osgb36_to_wgs84,
+    },
+    csa::{Stop, StopId},
+};
+
+/// Resolves the TIPLOCs and CRS codes used across a timetable to stops. Large
+/// stations have several TIPLOCs and minor CRS codes, which all resolve to the stop
+/// for their main CRS code.
+pub struct StopResolver {
+    tiplocs: HashMap<String, StopId>,
+    crs_codes: HashMap<String, StopId>,
+    stops: HashMap<StopId, Stop>,
+}
+
+impl StopResolver {
+    /// Groups the MSN's stations by CRS code, locating each group by
+    /// `station_overrides` or else by grid reference, and leaving out groups with
+    /// neither. `manual_mappings` maps extra TIPLOCs to CRS codes, taking precedence
+    /// over the MSN; mappings to CRS codes with no stop are logged and ignored.
+    pub fn new(
+        stations: &[Station],
+        aliases: &[Alias],
+        station_overrides: &[StationInfo],
+        manual_mappings: &HashMap<String, String>,
+    ) -> Self {
+        let station_overrides: HashMap<&str, &StationInfo> = station_overrides
+            .iter()
+            .map(|s| (s.crs.as_str(), s))
+            .collect();
+
+        // Groups in the order their CRS codes first appear, so stop IDs are stable
+        let mut groups: Vec<(&str, Vec<&Station>)> = vec![];
+        let mut group_index: HashMap<&str, usize> = HashMap::new();
+        for s in stations {
+            let i = *group_index.entry(s.crs.as_str()).or_insert_with(|| {
+                groups.push((s.crs.as_str(), vec![]));
+                groups.len() - 1
+            });
+            groups[i].1.push(s);
+        }
+
+        let located = groups.into_iter().filter_map(|(crs, group)| {
+            let main = main_station(&group);
+            if let Some(info) = station_overrides.get(crs) {
+                return Some((crs, group, main, info.name.clone(), info.lat, info.lon));
+            }
+
+            // Stations without a grid reference are given one of zero
+            let gridded = std::iter::once(main)
+                .chain(group.iter().copied())
+                .find(|s| s.easting != 0 || s.northing != 0)?;
+            let (lat, lon) = osgb36_to_wgs84(gridded.easting.into(), gridded.northing.into());
+            Some((crs, group, main, main.station_name.clone(), lat, lon))
+        });
+
+        let mut aliases_by_name: HashMap<&str, Vec<String>> = HashMap::new();
+        for a in aliases {
+            aliases_by_name
+                .entry(a.station_name.as_str())
+                .or_default()
+                .push(a.station_alias.clone());
+        }
+
+        let mut tiplocs = HashMap::new();
+        let mut crs_codes = HashMap::new();
+        let mut stops = HashMap::new();
+        for (i, (crs, group, main, name, lat, lon)) in located.enumerate() {
+            let id = StopId::new(i as u64);
+            let mut stop = Stop::new(name, lat, lon, main.min_change_time);
+            stop.codes = std::iter::once(crs.to_owned())
+                .chain(group.iter().map(|s| s.minor_crs.clone()))
+                .chain(group.iter().map(|s| s.tiploc.clone()))
+                .unique()
+                .collect();
+            stop.aliases = group
+                .iter()
+                .filter_map(|s| aliases_by_name.get(s.station_name.as_str()))
+                .flatten()
+                .cloned()
+                .unique()
+                .collect();
+            stops.insert(id, stop);
+            crs_codes.insert(crs.to_owned(), id);
+            for s in group {
+                tiplocs.insert(s.tiploc.clone(), id);
+                crs_codes.entry(s.minor_crs.clone()).or_insert(id);
+            }
+        }
+
+        for (tiploc, crs) in manual_mappings {
+            let Some(&id) = crs_codes.get(crs) else {
+                warn!("TIPLOC {tiploc} is mapped to CRS code {crs}, which has no stop");
+                continue;
+            };
+            tiplocs.insert(tiploc.clone(), id);
+            if let Some(stop) = stops.get_mut(&id) {
+                stop.codes.push(tiploc.clone());
+            }
+        }
+
+        Self {
+            tiplocs,
+            crs_codes,
+            stops,
+        }
+    }
+
+    pub fn tiploc(&self, tiploc: &str) -> Option<StopId> {
+        self.tiplocs.get(tiploc).copied()
+    }
+
+    /// The stop for a main or minor CRS code.
+    pub fn crs(&self, crs: &str) -> Option<StopId> {
+        self.crs_codes.get(crs).copied()
+    }
+
+    pub fn stops(&self) -> &HashMap<StopId, Stop> {
+        &self.stops
+    }
+
+    pub fn stop_mut(&mut self, id: StopId) -> Option<&mut Stop> {
+        self.stops.get_mut(&id)
+    }
+}
+
+/// The station that gives a group its name and change time: the one whose minor CRS
+/// code is the main one, skipping subsidiary TIPLOCs where possible.
+fn main_station<'a>(group: &[&'a Station]) -> &'a Station {
+    const SUBSIDIARY: u8 = 9;
+
+    group
+        .iter()
+        .find(|s| s.minor_crs == s.crs && s.interchange_status != SUBSIDIARY)
+        .or_else(|| group.iter().find(|s| s.interchange_status != SUBSIDIARY))
+        .copied()
+        .unwrap_or(group[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn station(name: &str, tiploc: &str, minor_crs: &str, crs: &str, status: u8) -> Station {
+        Station {
+            station_name: name.to_owned(),
+            interchange_status: status,
+            tiploc: tiploc.to_owned(),
+            minor_crs: minor_crs.to_owned(),
+            crs: crs.to_owned(),
+            easting: 529_000,
+            northing: 182_000,
+            min_change_time: TimeDelta::minutes(if status == 9 { 15 } else { 5 }),
+        }
+    }
+
+    fn stations() -> Vec<Station> {
+        vec![
+            station("STRATFORD LOW LEVEL", "STFDLL", "SRL", "SRA", 9),
+            station("STRATFORD", "STFD", "SRA", "SRA", 3),
+            station("MARYLAND", "MRYLAND", "MYL", "MYL", 2),
+            Station {
+                easting: 0,
+                northing: 0,
+                ..station("NOWHERE", "NOWHERE", "NWH", "NWH", 2)
+            },
+        ]
+    }
+
+    #[test]
+    fn resolves_every_tiploc_and_minor_crs_of_a_station_to_one_stop() {
+        let resolver = StopResolver::new(&stations(), &[], &[], &HashMap::new());
+
+        let stratford = resolver.crs("SRA").unwrap();
+        assert_eq!(resolver.crs("SRL"), Some(stratford));
+        assert_eq!(resolver.tiploc("STFD"), Some(stratford));
+        assert_eq!(resolver.tiploc("STFDLL"), Some(stratford));
+        assert_ne!(resolver.crs("MYL"), Some(stratford));
+
+        // Named and timed after the main station rather than the subsidiary TIPLOC
+        let stop = &resolver.stops()[&stratford];
+        assert_eq!(stop.name, "STRATFORD");
+        assert_eq!(stop.min_change_time, TimeDelta::minutes(5));
+        assert_eq!(stop.codes, ["SRA", "SRL", "STFDLL", "STFD"]);
+    }
+
+    #[test]
+    fn leaves_out_stations_without_a_location() {
+        let resolver = StopResolver::new(&stations(), &[], &[], &HashMap::new());
+
+        assert_eq!(resolver.stops().len(), 2);
+        assert!(resolver.crs("NWH").is_none());
+        assert!(resolver.tiploc("NOWHERE").is_none());
+    }
+
+    #[test]
+    fn locates_stations_by_override_before_grid_reference() {
+        let overrides = [StationInfo {
+            name: "Nowhere".to_owned(),
+            crs: "NWH".to_owned(),
+            lat: 52.0,
+            lon: -1.0,
+        }];
+
+        let resolver = StopResolver::new(&stations(), &[], &overrides, &HashMap::new());
+
+        let stop = &resolver.stops()[&resolver.tiploc("NOWHERE").unwrap()];
+        assert_eq!(stop.name, "Nowhere");
+        assert_eq!((stop.lat, stop.lon), (52.0, -1.0));
+    }
+
+    #[test]
+    fn maps_extra_tiplocs_to_known_crs_codes_only() {
+        let mappings = HashMap::from([
+            ("STFDJUB".to_owned(), "SRL".to_owned()),
+            ("UNKNOWN".to_owned(), "XXX".to_owned()),
+        ]);
+
+        let resolver = StopResolver::new(&stations(), &[], &[], &mappings);
+
+        let stratford = resolver.crs("SRA").unwrap();
+        assert_eq!(resolver.tiploc("STFDJUB"), Some(stratford));
+        assert!(
+            resolver.stops()[&stratford]
+                .codes
+                .contains(&"STFDJUB".to_owned())
+        );
+        assert!(resolver.tiploc("UNKNOWN").is_none());
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use chrono::TimeDelta;
+use std::io::{BufRead, BufReader, Read};
+
+/// Minimum time to change at a station from a train of one operator to a train of
+/// another, which takes precedence over the station's minimum change time.
+#[derive(Debug)]
+pub struct Interchange {
+    pub crs: String,
+    pub arriving_operator: String,
+    pub departing_operator: String,
+    pub time: TimeDelta,
+}
+
+pub fn parse_tsi<R: Read>(reader: R) -> Result<Vec<Interchange>> {
+    let reader = BufReader::new(reader);
+    let mut interchanges = vec![];
+    for line in reader.lines() {
+        let input = line?;
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        interchanges.push(parse_interchange(&input)?);
+    }
+
+    Ok(interchanges)
+}
+
+/// Parses a line such as `CLJ,SW,SN,5,Between South Western and Southern`.
+fn parse_interchange(input: &str) -> Result<Interchange> {
+    let mut fields = input.splitn(5, ',').map(str::trim);
+    let mut next = |name: &str| {
+        fields
+            .next()
+            .with_context(|| format!("missing {name} in interchange: {input}"))
+    };
+
+    let crs = next("CRS")?.to_owned();
+    let arriving_operator = next("arriving operator")?.to_owned();
+    let departing_operator = next("departing operator")?.to_owned();
+    let minutes = next("time")?;
+    let minutes: i64 = minutes
+        .parse()
+        .with_context(|| format!("invalid time minutes: {minutes}"))?;
+
+    Ok(Interchange {
+        crs,
+        arriving_operator,
+        departing_operator,
+        time: TimeDelta::minutes(minutes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_interchange() {
+        let interchange =
+            parse_interchange("CLJ,SW,SN,5,Between South Western, and Southern").unwrap();
+
+        assert_eq!(interchange.crs, "CLJ");
+        assert_eq!(interchange.arriving_operator, "SW");
+        assert_eq!(interchange.departing_operator, "SN");
+        assert_eq!(interchange.time, TimeDelta::minutes(5));
+    }
+
+    #[test]
+    fn rejects_interchange_without_time() {
+        assert!(parse_interchange("CLJ,SW,SN").is_err());
+        assert!(parse_interchange("CLJ,SW,SN,five,").is_err());
+    }
+}
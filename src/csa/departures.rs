@@ -0,0 +1,150 @@
+use std::fmt;
+
+use anyhow::ensure;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use serde::Serialize;
+
+use super::{
+    MAX_HORIZON, QueryOptions, ServiceInfo, StopId, TransportNetwork, TripId,
+    journey::{JourneyStop, describe_platform, describe_service},
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Departure {
+    pub trip_id: TripId,
+    pub departure_time: NaiveDateTime,
+    pub platform: Option<String>,
+    /// Where the trip ends.
+    pub destination: JourneyStop,
+    pub service: ServiceInfo,
+}
+
+/// Trips that can be boarded at one stop, in order of departure.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepartureBoard {
+    pub stop: JourneyStop,
+    pub departures: Vec<Departure>,
+}
+
+impl TransportNetwork {
+    /// Trips that can be boarded at `stop_id` within `window` of `time` on `date`, or
+    /// `None` if there is no such stop. The window can be no longer than a query's
+    /// horizon.
+    pub fn departures(
+        &self,
+        stop_id: StopId,
+        date: NaiveDate,
+        time: NaiveTime,
+        window: TimeDelta,
+        options: &QueryOptions,
+    ) -> anyhow::Result<Option<DepartureBoard>> {
+        ensure!(
+            window <= MAX_HORIZON,
+            "departure window must be at most {} days",
+            MAX_HORIZON.num_days()
+        );
+        if !self.stops.contains_key(&stop_id) {
+            return Ok(None);
+        }
+
+        let start = NaiveDateTime::new(date, time);
+        let departures = self
+            .connections_departing_between(start, start + window)
+            .filter(|(_, c, service_date)| {
+                c.from_stop_id == stop_id
+                    && c.can_board
+                    && options.allows(self.service(c))
+                    && self.calendar.runs_on(c.trip_id, *service_date)
+            })
+            .map(|(_, c, service_date)| Departure {
+                trip_id: c.trip_id,
+                departure_time: c.departure_date_time(service_date),
+                platform: self.platform(c.departure_platform),
+                destination: self.journey_stop(self.termini[&c.trip_id]),
+                service: self.service(c).clone(),
+            })
+            .collect();
+
+        Ok(Some(DepartureBoard {
+            stop: self.journey_stop(stop_id),
+            departures,
+        }))
+    }
+}
+
+impl fmt::Display for DepartureBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Departures from {}", self.stop.name)?;
+
+        for departure in &self.departures {
+            writeln!(
+                f,
+                "  {} {} to {}{}",
+                departure.departure_time.format("%H:%M"),
+                describe_service(departure.trip_id, &departure.service),
+                departure.destination.name,
+                describe_platform(departure.platform.as_deref())
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::test_network::{TestNetwork, date, time};
+
+    fn board(network: &TransportNetwork, stop: u64) -> Option<DepartureBoard> {
+        network
+            .departures(
+                StopId::new(stop),
+                date(),
+                time("09:00"),
+                TimeDelta::hours(1),
+                &QueryOptions::default(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn lists_trips_leaving_within_the_window_with_where_they_end() {
+        let network = TestNetwork::new(4)
+            .trip(0, &[(0, "09:00"), (1, "09:10"), (2, "09:20")])
+            .trip(1, &[(1, "09:05"), (0, "09:15")])
+            .trip(2, &[(1, "10:30"), (2, "10:40")])
+            .trip(3, &[(3, "09:20"), (1, "09:30")])
+            .build();
+
+        let board = board(&network, 1).unwrap();
+
+        assert_eq!(board.stop.name, "S1");
+        let departures: Vec<(NaiveTime, u64, &str)> = board
+            .departures
+            .iter()
+            .map(|d| {
+                (
+                    d.departure_time.time(),
+                    d.trip_id.index(),
+                    d.destination.name.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            departures,
+            [(time("09:05"), 1, "S0"), (time("09:10"), 0, "S2")]
+        );
+    }
+
+    #[test]
+    fn has_no_board_for_an_unknown_stop() {
+        let network = TestNetwork::new(2)
+            .trip(0, &[(0, "09:00"), (1, "09:10")])
+            .build();
+
+        assert!(board(&network, 5).is_none());
+    }
+}
@@ -0,0 +1,436 @@
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use serde::Serialize;
+
+use super::{
+    CsaState, JourneyPointer, Origin, QueryOptions, ServiceInfo, ServiceKind, StopId,
+    StoredConnection, TransportNetwork, TripId, state::Boarding,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JourneyStop {
+    pub stop_id: StopId,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Leg {
+    /// Walk from the origin to the first stop.
+    Access {
+        to: JourneyStop,
+        departure_time: NaiveDateTime,
+        arrival_time: NaiveDateTime,
+    },
+    Trip {
+        trip_id: TripId,
+        from: JourneyStop,
+        to: JourneyStop,
+        departure_time: NaiveDateTime,
+        arrival_time: NaiveDateTime,
+        departure_platform: Option<String>,
+        arrival_platform: Option<String>,
+        /// Details of the trip on departure from `from`.
+        service: ServiceInfo,
+        /// Stops along the leg after which the trip's details change.
+        service_changes: Vec<ServiceChange>,
+    },
+    Transfer {
+        from: JourneyStop,
+        to: JourneyStop,
+        departure_time: NaiveDateTime,
+        arrival_time: NaiveDateTime,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceChange {
+    pub at: JourneyStop,
+    pub service: ServiceInfo,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Journey {
+    pub departure_time: NaiveDateTime,
+    pub arrival_time: NaiveDateTime,
+    pub changes: u32,
+    pub legs: Vec<Leg>,
+}
+
+impl TransportNetwork {
+    /// Finds the earliest-arrival itinerary from `origin` to `destination`.
+    pub fn journey(
+        &self,
+        origin: &Origin,
+        date: NaiveDate,
+        departure_time: NaiveTime,
+        destination: StopId,
+        options: &QueryOptions,
+    ) -> Option<Journey> {
+        let access = self.access_stops(origin, options);
+        let mut csa = CsaState::new();
+        self.scan(&mut csa, &access, date, departure_time, options);
+
+        self.reconstruct_journey(&csa, NaiveDateTime::new(date, departure_time), destination)
+    }
+
+    /// Follows journey pointers back from the earliest arrival at `destination` to
+    /// the origin.
+    fn reconstruct_journey(
+        &self,
+        csa: &CsaState,
+        departure: NaiveDateTime,
+        destination: StopId,
+    ) -> Option<Journey> {
+        let arrival = csa.earliest_arrival(destination)?;
+        let mut legs = vec![];
+        let mut label = arrival;
+
+        loop {
+            let leg = match label.via {
+                JourneyPointer::Access { walk } => {
+                    legs.push(Leg::Access {
+                        to: self.journey_stop(label.stop_id),
+                        departure_time: departure,
+                        arrival_time: departure + walk,
+                    });
+                    legs.reverse();
+
+                    return Some(Journey {
+                        departure_time: departure,
+                        arrival_time: arrival.time,
+                        changes: arrival.changes(),
+                        legs,
+                    });
+                }
+                JourneyPointer::Connection {
+                    service_date,
+                    enter,
+                    exit,
+                    mut boarded_from,
+                } => {
+                    legs.push(self.trip_leg(service_date, enter, exit));
+
+                    // Trips stayed on board through where they join or divide are
+                    // separate legs, with no transfer between them
+                    let from = loop {
+                        match boarded_from {
+                            Boarding::Label(from) => break from,
+                            Boarding::StaySeated(ride) => {
+                                let ride = csa.ride(ride);
+                                legs.push(self.trip_leg(ride.service_date, ride.enter, ride.exit));
+                                boarded_from = ride.boarded_from;
+                            }
+                        }
+                    };
+                    label = csa.label(from);
+                    continue;
+                }
+                JourneyPointer::Transfer { from } => {
+                    let to = label;
+                    label = csa.label(from);
+
+                    Leg::Transfer {
+                        from: self.journey_stop(label.stop_id),
+                        to: self.journey_stop(to.stop_id),
+                        departure_time: label.time,
+                        arrival_time: to.time,
+                    }
+                }
+            };
+
+            legs.push(leg);
+        }
+    }
+
+    /// Riding a trip on `service_date` from connection `enter` to connection `exit`.
+    fn trip_leg(&self, service_date: NaiveDate, enter: usize, exit: usize) -> Leg {
+        let ridden: Vec<&StoredConnection> = self.connections[enter..=exit]
+            .iter()
+            .filter(|c| c.trip_id == self.connections[enter].trip_id)
+            .collect();
+        let (first, last) = (ridden[0], ridden[ridden.len() - 1]);
+
+        let service_changes = ridden
+            .windows(2)
+            .filter(|pair| pair[0].service != pair[1].service)
+            .map(|pair| ServiceChange {
+                at: self.journey_stop(pair[1].from_stop_id),
+                service: self.service(pair[1]).clone(),
+            })
+            .collect();
+
+        Leg::Trip {
+            trip_id: first.trip_id,
+            from: self.journey_stop(first.from_stop_id),
+            to: self.journey_stop(last.to_stop_id),
+            departure_time: first.departure_date_time(service_date),
+            arrival_time: last.arrival_date_time(service_date),
+            departure_platform: self.platform(first.departure_platform),
+            arrival_platform: self.platform(last.arrival_platform),
+            service: self.service(first).clone(),
+            service_changes,
+        }
+    }
+
+    pub(super) fn journey_stop(&self, stop_id: StopId) -> JourneyStop {
+        JourneyStop {
+            stop_id,
+            name: self.stop(stop_id).name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Journey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes = |d: TimeDelta| d.num_minutes();
+
+        writeln!(
+            f,
+            "Depart {} arrive {} ({} min, {} changes)",
+            self.departure_time.format("%H:%M"),
+            self.arrival_time.format("%H:%M"),
+            minutes(self.arrival_time - self.departure_time),
+            self.changes
+        )?;
+
+        for leg in &self.legs {
+            match leg {
+                Leg::Access {
+                    to,
+                    departure_time,
+                    arrival_time,
+                } => writeln!(
+                    f,
+                    "  {} walk to {} ({} min)",
+                    departure_time.format("%H:%M"),
+                    to.name,
+                    minutes(*arrival_time - *departure_time)
+                )?,
+                Leg::Trip {
+                    trip_id,
+                    from,
+                    to,
+                    departure_time,
+                    arrival_time,
+                    departure_platform,
+                    arrival_platform,
+                    service,
+                    service_changes,
+                } => {
+                    writeln!(
+                        f,
+                        "  {} {} from {}{} to {}{}, arriving {}",
+                        departure_time.format("%H:%M"),
+                        describe_service(*trip_id, service),
+                        from.name,
+                        describe_platform(departure_platform.as_deref()),
+                        to.name,
+                        describe_platform(arrival_platform.as_deref()),
+                        arrival_time.format("%H:%M")
+                    )?;
+                    for change in service_changes {
+                        writeln!(
+                            f,
+                            "      continues from {} as {}",
+                            change.at.name,
+                            describe_service(*trip_id, &change.service)
+                        )?;
+                    }
+                }
+                Leg::Transfer {
+                    from,
+                    to,
+                    departure_time,
+                    arrival_time,
+                } => writeln!(
+                    f,
+                    "  {} transfer from {} to {} ({} min)",
+                    departure_time.format("%H:%M"),
+                    from.name,
+                    to.name,
+                    minutes(*arrival_time - *departure_time)
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Such as " (platform 4)", or nothing if the platform isn't known.
+pub(super) fn describe_platform(platform: Option<&str>) -> String {
+    platform.map_or_else(String::new, |p| format!(" (platform {p})"))
+}
+
+/// Such as "trip 12 (XX, operator VT)".
+pub(super) fn describe_service(trip_id: TripId, service: &ServiceInfo) -> String {
+    let details: Vec<String> = [
+        service.category.clone(),
+        service.operator.as_ref().map(|o| format!("operator {o}")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let kind = match service.kind {
+        ServiceKind::Train => "trip",
+        ServiceKind::ReplacementBus => "replacement bus",
+        ServiceKind::Bus => "bus",
+        ServiceKind::Ship => "ship",
+        ServiceKind::Other => "service",
+    };
+
+    if details.is_empty() {
+        format!("{kind} {}", trip_id.0)
+    } else {
+        format!("{kind} {} ({})", trip_id.0, details.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::{
+        Transfer, TransferMode,
+        test_network::{TestNetwork, date, date_time, time},
+    };
+
+    fn journey(network: &TransportNetwork, to: u64) -> Journey {
+        network
+            .journey(
+                &Origin::Stop(StopId::new(0)),
+                date(),
+                time("08:00"),
+                StopId::new(to),
+                &QueryOptions::default(),
+            )
+            .expect("a journey")
+    }
+
+    /// Such as "trip 1 S0 08:10 S1 08:30".
+    fn describe(leg: &Leg) -> String {
+        let hh_mm = |t: &NaiveDateTime| t.format("%H:%M").to_string();
+        match leg {
+            Leg::Access { to, .. } => format!("access {}", to.name),
+            Leg::Trip {
+                trip_id,
+                from,
+                to,
+                departure_time,
+                arrival_time,
+                ..
+            } => format!(
+                "trip {} {} {} {} {}",
+                trip_id.index(),
+                from.name,
+                hh_mm(departure_time),
+                to.name,
+                hh_mm(arrival_time)
+            ),
+            Leg::Transfer {
+                from,
+                to,
+                departure_time,
+                arrival_time,
+            } => format!(
+                "transfer {} {} {} {}",
+                from.name,
+                hh_mm(departure_time),
+                to.name,
+                hh_mm(arrival_time)
+            ),
+        }
+    }
+
+    #[test]
+    fn reconstructs_trips_and_transfers_in_order() {
+        let network = TestNetwork::new(4)
+            .trip(0, &[(0, "08:10"), (1, "08:30")])
+            .trip(1, &[(2, "08:40"), (3, "09:00")])
+            .transfer(Transfer {
+                from_stop_id: StopId::new(1),
+                to_stop_id: StopId::new(2),
+                transfer_time: TimeDelta::minutes(5),
+                validity: None,
+                mode: TransferMode::Walk,
+                priority: None,
+            })
+            .build();
+
+        let journey = journey(&network, 3);
+
+        let legs: Vec<String> = journey.legs.iter().map(describe).collect();
+        assert_eq!(
+            legs,
+            [
+                "access S0",
+                "trip 0 S0 08:10 S1 08:30",
+                "transfer S1 08:30 S2 08:35",
+                "trip 1 S2 08:40 S3 09:00",
+            ]
+        );
+        assert_eq!(journey.departure_time, date_time("08:00"));
+        assert_eq!(journey.arrival_time, date_time("09:00"));
+        assert_eq!(journey.changes, 1);
+    }
+
+    #[test]
+    fn rides_a_trip_through_stops_in_one_leg() {
+        let network = TestNetwork::new(5)
+            .trip(0, &[(0, "08:10"), (1, "08:20"), (2, "08:30")])
+            .trip(1, &[(3, "08:15"), (4, "08:25")])
+            .build();
+
+        let journey = journey(&network, 2);
+
+        let legs: Vec<String> = journey.legs.iter().map(describe).collect();
+        assert_eq!(legs, ["access S0", "trip 0 S0 08:10 S2 08:30"]);
+        assert_eq!(journey.changes, 0);
+    }
+
+    #[test]
+    fn stays_seated_where_trips_join_or_divide_without_a_change() {
+        let network = TestNetwork::new(3)
+            .trip(0, &[(0, "08:10"), (1, "08:30")])
+            .trip(1, &[(1, "08:35"), (2, "09:00")])
+            .stay_seated(0, 1, 1)
+            .change_time(1, 10)
+            .build();
+
+        let journey = journey(&network, 2);
+
+        let legs: Vec<String> = journey.legs.iter().map(describe).collect();
+        assert_eq!(
+            legs,
+            [
+                "access S0",
+                "trip 0 S0 08:10 S1 08:30",
+                "trip 1 S1 08:35 S2 09:00",
+            ]
+        );
+        assert_eq!(journey.changes, 0);
+    }
+
+    #[test]
+    fn counts_changes_between_trips() {
+        let network = TestNetwork::new(4)
+            .trip(0, &[(0, "08:10"), (1, "08:20")])
+            .trip(1, &[(1, "08:25"), (2, "08:35")])
+            .trip(2, &[(2, "08:40"), (3, "08:50")])
+            .build();
+
+        let journey = journey(&network, 3);
+
+        assert_eq!(journey.legs.len(), 4);
+        assert_eq!(journey.changes, 2);
+    }
+}
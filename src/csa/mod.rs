@@ -0,0 +1,1341 @@
+use std::fs::File;
+use std::io::{BufReader, prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::ensure;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use flate2::{Compression, bufread::ZlibDecoder, write::ZlibEncoder};
+use geojson::{Feature, FeatureCollection, ser::serialize_geometry};
+use itertools::Itertools;
+use kiddo::{KdTree, SquaredEuclidean};
+use serde::{Deserialize, Serialize};
+
+use crate::adapters::CsaAdapter;
+use search::SearchIndex;
+use state::{BoardedTrip, Boarding, ChangeGroup, CsaState, JourneyPointer, Label, Ride};
+
+mod departures;
+mod journey;
+mod profile;
+mod reverse;
+mod search;
+mod state;
+#[cfg(test)]
+mod test_network;
+
+pub use departures::DepartureBoard;
+pub use journey::Journey;
+pub use search::StopMatch;
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, Default, PartialOrd, Ord, Deserialize, Serialize,
+)]
+pub struct StopId(u64);
+
+impl StopId {
+    pub fn new(idx: u64) -> Self {
+        Self(idx)
+    }
+
+    pub fn index(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct TripId(u64);
+
+impl TripId {
+    pub fn new(idx: u64) -> Self {
+        Self(idx)
+    }
+
+    pub fn index(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArrivalTime {
+    pub stop_id: StopId,
+    pub stop_name: String,
+    pub arrival_time: i64,
+    /// Number of changes between trips on the fastest journey.
+    pub changes: u32,
+    /// Details of the trip last ridden to get here, or `None` if it was reached on foot.
+    pub arrived_by: Option<ServiceInfo>,
+    #[serde(serialize_with = "serialize_geometry")]
+    pub geometry: geo_types::Point<f64>,
+}
+
+pub(crate) const WALKING_SPEED_M_S: f64 = 1.4;
+
+/// Largest access radius a query may use, which bounds how many stops each query
+/// starts from.
+const MAX_ACCESS_RADIUS_M: f64 = 20_000.0;
+
+/// Longest time a query may scan for, which bounds how many service days each scan
+/// goes through.
+pub const MAX_HORIZON: TimeDelta = TimeDelta::days(7);
+
+/// Per-query settings shared by every kind of scan.
+#[derive(Clone, Debug)]
+pub struct QueryOptions {
+    /// Maximum number of changes between trips, or unlimited if `None`.
+    pub max_transfers: Option<u32>,
+    /// How far past the departure time (or before the deadline) to scan.
+    pub horizon: TimeDelta,
+    /// Which operators' trips may be used.
+    pub operators: OperatorFilter,
+    /// Kinds of service that may not be used.
+    pub excluded_kinds: HashSet<ServiceKind>,
+    /// Ways of making transfers that may be used.
+    pub transfer_modes: HashSet<TransferMode>,
+    /// How far from the origin, in metres as the crow flies, to look for stops.
+    pub access_radius: f64,
+    /// Speed of getting between the origin and its stops, in metres per second.
+    pub access_speed: f64,
+    /// How much longer the route to a stop is than the straight line to it.
+    pub detour_factor: f64,
+    /// Time added to the journey to every stop reached from the origin, such as for
+    /// buying a ticket or parking a bike.
+    pub access_penalty: TimeDelta,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            max_transfers: None,
+            horizon: TimeDelta::days(1),
+            operators: OperatorFilter::All,
+            excluded_kinds: HashSet::new(),
+            transfer_modes: TransferMode::ALL.into_iter().collect(),
+            access_radius: 1000.0,
+            access_speed: WALKING_SPEED_M_S,
+            detour_factor: 1.0,
+            access_penalty: TimeDelta::zero(),
+        }
+    }
+}
+
+impl QueryOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.horizon > TimeDelta::zero(), "horizon must be positive");
+        ensure!(
+            self.horizon <= MAX_HORIZON,
+            "horizon must be at most {} days",
+            MAX_HORIZON.num_days()
+        );
+        ensure!(
+            self.access_radius.is_finite() && self.access_radius > 0.0,
+            "access radius must be positive"
+        );
+        ensure!(
+            self.access_radius <= MAX_ACCESS_RADIUS_M,
+            "access radius must be at most {MAX_ACCESS_RADIUS_M} metres"
+        );
+        ensure!(
+            self.access_speed.is_finite() && self.access_speed > 0.0,
+            "access speed must be positive"
+        );
+        ensure!(
+            self.detour_factor.is_finite() && self.detour_factor >= 1.0,
+            "detour factor must be at least 1"
+        );
+        ensure!(
+            self.access_penalty >= TimeDelta::zero(),
+            "access penalty can't be negative"
+        );
+        Ok(())
+    }
+
+    /// Maximum number of trips a journey may use.
+    fn max_trips(&self) -> u32 {
+        self.max_transfers.map_or(u32::MAX, |m| m.saturating_add(1))
+    }
+
+    /// Whether a journey may use connections of `service`.
+    fn allows(&self, service: &ServiceInfo) -> bool {
+        self.operators.allows(service.operator.as_deref())
+            && !self.excluded_kinds.contains(&service.kind)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum OperatorFilter {
+    #[default]
+    All,
+    /// Only these operators; trips with no known operator are left out.
+    Only(HashSet<String>),
+    Except(HashSet<String>),
+}
+
+impl OperatorFilter {
+    fn allows(&self, operator: Option<&str>) -> bool {
+        match self {
+            OperatorFilter::All => true,
+            OperatorFilter::Only(operators) => operator.is_some_and(|o| operators.contains(o)),
+            OperatorFilter::Except(operators) => operator.is_none_or(|o| !operators.contains(o)),
+        }
+    }
+}
+
+/// Where a query starts, or ends for arrive-by queries.
+#[derive(Clone, Copy, Debug)]
+pub enum Origin {
+    /// An arbitrary point, walking to and from the stops near it.
+    Point { lat: f64, lon: f64 },
+    /// A stop, starting there straight away.
+    Stop(StopId),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Point { lat, lon } => write!(f, "({lat}, {lon})"),
+            Origin::Stop(stop_id) => write!(f, "stop {}", stop_id.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Stop {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Minimum time needed to change between trips at this stop.
+    pub min_change_time: TimeDelta,
+    /// Times needed to change between trips of particular operators, which take
+    /// precedence over `min_change_time`.
+    pub interchange_times: Vec<InterchangeTime>,
+    /// Codes identifying the stop, such as its CRS code and TIPLOCs.
+    pub codes: Vec<String>,
+    /// Other names the stop is known by.
+    pub aliases: Vec<String>,
+}
+
+impl Stop {
+    pub fn new(name: String, lat: f64, lon: f64, min_change_time: TimeDelta) -> Self {
+        Self {
+            name,
+            lat,
+            lon,
+            min_change_time,
+            interchange_times: vec![],
+            codes: vec![],
+            aliases: vec![],
+        }
+    }
+
+    /// Time needed to change from a trip run by `arriving` to one run by `departing`.
+    pub fn change_time(&self, arriving: Option<&str>, departing: Option<&str>) -> TimeDelta {
+        self.interchange_times
+            .iter()
+            .find(|i| {
+                arriving == Some(i.arriving_operator.as_str())
+                    && departing == Some(i.departing_operator.as_str())
+            })
+            .map_or(self.min_change_time, |i| i.time)
+    }
+
+    /// The shortest time needed to change between any two trips at this stop.
+    pub fn shortest_change_time(&self) -> TimeDelta {
+        self.interchange_times
+            .iter()
+            .fold(self.min_change_time, |shortest, i| shortest.min(i.time))
+    }
+}
+
+/// Time needed to change from a trip of one operator to a trip of another.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InterchangeTime {
+    pub arriving_operator: String,
+    pub departing_operator: String,
+    pub time: TimeDelta,
+}
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Connection {
+    pub trip_id: TripId,
+    pub from_stop_id: StopId,
+    pub to_stop_id: StopId,
+    /// Seconds since the start of the trip's service day. This goes past 24 hours
+    /// for trips that run through midnight.
+    pub departure_time: u32,
+    /// Seconds since the start of the trip's service day, as for `departure_time`.
+    pub arrival_time: u32,
+    /// Whether passengers may board at `from_stop_id`.
+    pub can_board: bool,
+    /// Whether passengers may alight at `to_stop_id`.
+    pub can_alight: bool,
+    pub service: ServiceInfo,
+    /// Platform the trip departs `from_stop_id` from, if known.
+    pub departure_platform: Option<String>,
+    /// Platform the trip arrives at `to_stop_id` on, if known.
+    pub arrival_platform: Option<String>,
+}
+
+/// A connection as the network keeps it. Service details and platforms are the same
+/// for many connections, so each is stored once in the network and referred to here
+/// by its index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredConnection {
+    trip_id: TripId,
+    from_stop_id: StopId,
+    to_stop_id: StopId,
+    departure_time: u32,
+    arrival_time: u32,
+    can_board: bool,
+    can_alight: bool,
+    service: u32,
+    departure_platform: Option<u32>,
+    arrival_platform: Option<u32>,
+}
+
+/// Details of a trip in force on one connection. Trains can change these en route,
+/// for instance becoming a bus part of the way.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceInfo {
+    /// Code of the company running the trip, such as an ATOC code.
+    pub operator: Option<String>,
+    /// Category code, such as "XX" for an express passenger train.
+    pub category: Option<String>,
+    /// Code identifying the service, such as a CIF train service code.
+    pub service_code: Option<String>,
+    pub kind: ServiceKind,
+}
+
+/// What runs a trip, for telling trains apart from buses and boats in the same
+/// timetable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceKind {
+    /// A train, or other rail service such as a metro or tram.
+    Train,
+    /// A bus standing in for a train.
+    ReplacementBus,
+    /// A bus service in its own right.
+    Bus,
+    Ship,
+    /// Anything else, such as an aerial lift, a taxi or a flight.
+    Other,
+}
+
+impl FromStr for ServiceKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "train" => ServiceKind::Train,
+            "replacement-bus" => ServiceKind::ReplacementBus,
+            "bus" => ServiceKind::Bus,
+            "ship" => ServiceKind::Ship,
+            "other" => ServiceKind::Other,
+            _ => anyhow::bail!("unexpected service kind: {s}"),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Transfer {
+    pub from_stop_id: StopId,
+    pub to_stop_id: StopId,
+    pub transfer_time: TimeDelta,
+    /// When the transfer can be started, or `None` if it can be used at any time.
+    pub validity: Option<Validity>,
+    pub mode: TransferMode,
+    /// Which of several transfers between the same stops applies when more than one
+    /// can be used, with 1 taking precedence over 2 and so on, or `None` if the
+    /// transfer never competes with another.
+    pub priority: Option<u8>,
+}
+
+impl Transfer {
+    fn usable_at(&self, departure: NaiveDateTime, options: &QueryOptions) -> bool {
+        options.transfer_modes.contains(&self.mode)
+            && self.validity.as_ref().is_none_or(|v| v.contains(departure))
+    }
+
+    /// Whether `other` applies instead of this transfer when both can be used.
+    fn outranked_by(&self, other: &Transfer) -> bool {
+        let better = matches!((self.priority, other.priority), (Some(p), Some(q)) if q < p);
+        better && self.from_stop_id == other.from_stop_id && self.to_stop_id == other.to_stop_id
+    }
+}
+
+/// How a transfer between stops is made.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum TransferMode {
+    Bus,
+    Tube,
+    #[default]
+    Walk,
+    Ferry,
+    Metro,
+    Tram,
+    Taxi,
+    /// Changing within a station.
+    Transfer,
+}
+
+impl TransferMode {
+    pub const ALL: [TransferMode; 8] = [
+        TransferMode::Bus,
+        TransferMode::Tube,
+        TransferMode::Walk,
+        TransferMode::Ferry,
+        TransferMode::Metro,
+        TransferMode::Tram,
+        TransferMode::Taxi,
+        TransferMode::Transfer,
+    ];
+}
+
+impl FromStr for TransferMode {
+    type Err = anyhow::Error;
+
+    /// Parses a mode as written in ALF files, such as `TUBE`, ignoring case.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "BUS" => TransferMode::Bus,
+            "TUBE" => TransferMode::Tube,
+            "WALK" => TransferMode::Walk,
+            "FERRY" => TransferMode::Ferry,
+            "METRO" => TransferMode::Metro,
+            "TRAM" => TransferMode::Tram,
+            "TAXI" => TransferMode::Taxi,
+            "TRANSFER" => TransferMode::Transfer,
+            _ => anyhow::bail!("unexpected value for mode: {s}"),
+        })
+    }
+}
+
+/// A daily time window, optionally limited to a date range and days of the week.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Validity {
+    pub start_time: NaiveTime,
+    /// End of the window, inclusive. Windows ending before they start run past midnight.
+    pub end_time: NaiveTime,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub days_run: Option<[bool; 7]>,
+}
+
+impl Validity {
+    fn contains(&self, time: NaiveDateTime) -> bool {
+        let t = time.time();
+        // The date the window started on, which is the day before for the part of an
+        // overnight window after midnight
+        let date = if self.start_time <= self.end_time {
+            if t < self.start_time || t > self.end_time {
+                return false;
+            }
+            time.date()
+        } else if t >= self.start_time {
+            time.date()
+        } else if t <= self.end_time {
+            time.date() - TimeDelta::days(1)
+        } else {
+            return false;
+        };
+
+        let in_range =
+            self.start_date.is_none_or(|d| d <= date) && self.end_date.is_none_or(|d| date <= d);
+        let valid_weekday = self
+            .days_run
+            .is_none_or(|days| days[date.weekday().days_since(chrono::Weekday::Mon) as usize]);
+
+        in_range && valid_weekday
+    }
+}
+
+/// Two trips that join or divide at a stop, so passengers arriving on `from_trip_id`
+/// can stay on board for `to_trip_id` without making a change.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StaySeated {
+    pub from_trip_id: TripId,
+    pub to_trip_id: TripId,
+    pub stop_id: StopId,
+    /// Days from the service day of `from_trip_id` to that of `to_trip_id`.
+    pub day_offset: i64,
+    /// Whether `services` and `cancellations` are dates of `to_trip_id` rather than
+    /// `from_trip_id`.
+    pub onward_dates: bool,
+    pub services: Vec<Service>,
+    pub cancellations: Vec<Service>,
+}
+
+impl StaySeated {
+    /// The service day of `to_trip_id` when `from_trip_id` runs on `from_date`.
+    fn onward_date(&self, from_date: NaiveDate) -> NaiveDate {
+        from_date + TimeDelta::days(self.day_offset)
+    }
+
+    /// Whether the trips are linked when `from_trip_id` runs on `from_date`.
+    fn applies(&self, from_date: NaiveDate) -> bool {
+        let date = if self.onward_dates {
+            self.onward_date(from_date)
+        } else {
+            from_date
+        };
+
+        self.services.iter().any(|s| s.runs_on(date))
+            && !self.cancellations.iter().any(|s| s.runs_on(date))
+    }
+}
+
+impl StoredConnection {
+    /// Departure time when the trip runs on the service day `date`.
+    fn departure_date_time(&self, date: NaiveDate) -> NaiveDateTime {
+        service_date_time(date, self.departure_time)
+    }
+
+    /// Arrival time when the trip runs on the service day `date`.
+    fn arrival_date_time(&self, date: NaiveDate) -> NaiveDateTime {
+        service_date_time(date, self.arrival_time)
+    }
+}
+
+fn service_date_time(date: NaiveDate, seconds: u32) -> NaiveDateTime {
+    NaiveDateTime::new(date, NaiveTime::MIN) + TimeDelta::seconds(seconds.into())
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Calendar {
+    services: HashMap<TripId, Vec<Service>>,
+    cancellations: HashMap<TripId, Vec<Service>>,
+}
+
+impl Calendar {
+    pub fn new(
+        services: HashMap<TripId, Vec<Service>>,
+        cancellations: HashMap<TripId, Vec<Service>>,
+    ) -> Self {
+        Self {
+            services,
+            cancellations,
+        }
+    }
+
+    /// Adds the services and cancellations of `other`, renumbering its trips with
+    /// `trip_id`.
+    pub fn extend(&mut self, other: Calendar, mut trip_id: impl FnMut(TripId) -> TripId) {
+        for (id, services) in other.services {
+            self.services
+                .entry(trip_id(id))
+                .or_default()
+                .extend(services);
+        }
+        for (id, cancellations) in other.cancellations {
+            self.cancellations
+                .entry(trip_id(id))
+                .or_default()
+                .extend(cancellations);
+        }
+    }
+
+    pub(crate) fn runs_on(&self, trip_id: TripId, date: NaiveDate) -> bool {
+        let service_runs = self
+            .services
+            .get(&trip_id)
+            .map(|services| services.iter().any(|s| s.runs_on(date)))
+            .unwrap_or(false);
+
+        let cancelled = self
+            .cancellations
+            .get(&trip_id)
+            .map(|c| c.iter().any(|s| s.runs_on(date)))
+            .unwrap_or(false);
+
+        service_runs && !cancelled
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Service {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    runs_on: [bool; 7],
+}
+
+impl Service {
+    pub fn new(start_date: NaiveDate, end_date: NaiveDate, runs_on: [bool; 7]) -> Self {
+        Self {
+            start_date,
+            end_date,
+            runs_on,
+        }
+    }
+
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        let in_range = self.start_date <= date && self.end_date >= date;
+        let valid_weekday = self.runs_on[date.weekday().days_since(chrono::Weekday::Mon) as usize];
+
+        in_range && valid_weekday
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransportNetwork {
+    tree: kiddo::KdTree<f64, 3>,
+    stops: HashMap<StopId, Stop>,
+    connections: Vec<StoredConnection>,
+    /// Indices into `connections`, sorted by arrival time.
+    arrival_order: Vec<usize>,
+    /// Service details referred to by `connections`.
+    services: Vec<ServiceInfo>,
+    /// Platforms referred to by `connections`.
+    platforms: Vec<String>,
+    /// The stop at which each trip ends.
+    termini: HashMap<TripId, StopId>,
+    transfers: HashMap<StopId, Vec<Transfer>>,
+    /// The same transfers as `transfers`, keyed by the stop they lead to.
+    reverse_transfers: HashMap<StopId, Vec<Transfer>>,
+    /// Stay-seated links, keyed by the trip they continue onto.
+    stay_seated: HashMap<TripId, Vec<StaySeated>>,
+    /// The same links as `stay_seated`, keyed by the trip they continue from.
+    reverse_stay_seated: HashMap<TripId, Vec<StaySeated>>,
+    calendar: Calendar,
+    search: SearchIndex,
+}
+
+impl TransportNetwork {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let mut d = ZlibDecoder::new(reader);
+        let mut bytes = vec![];
+        d.read_to_end(&mut bytes)?;
+
+        Ok(postcard::from_bytes(&bytes)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let bytes = postcard::to_stdvec(self)?;
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&bytes)?;
+        let bytes = e.finish()?;
+        std::fs::write(path, &bytes)?;
+        Ok(())
+    }
+
+    pub fn from_adapter<A: CsaAdapter>(adapter: &A) -> Result<Self, A::Error> {
+        let stops = adapter.stops()?;
+        let connections = adapter.connections()?;
+
+        // A trip ends where its latest connection arrives
+        let mut last_arrivals: HashMap<TripId, &Connection> = HashMap::new();
+        for c in &connections {
+            let last = last_arrivals.entry(c.trip_id).or_insert(c);
+            if (c.arrival_time, c.departure_time) > (last.arrival_time, last.departure_time) {
+                *last = c;
+            }
+        }
+        let termini = last_arrivals
+            .into_iter()
+            .map(|(trip_id, c)| (trip_id, c.to_stop_id))
+            .collect();
+
+        let mut services = Interner::new();
+        let mut platforms = Interner::new();
+        let mut connections: Vec<StoredConnection> = connections
+            .into_iter()
+            .map(|c| StoredConnection {
+                trip_id: c.trip_id,
+                from_stop_id: c.from_stop_id,
+                to_stop_id: c.to_stop_id,
+                departure_time: c.departure_time,
+                arrival_time: c.arrival_time,
+                can_board: c.can_board,
+                can_alight: c.can_alight,
+                service: services.intern(c.service),
+                departure_platform: c.departure_platform.map(|p| platforms.intern(p)),
+                arrival_platform: c.arrival_platform.map(|p| platforms.intern(p)),
+            })
+            .collect();
+        // Connections of a trip that leave at the same second must stay in the order
+        // the trip makes them, so the sorts are stable and break ties on the other time
+        connections.sort_by_key(|c| (c.departure_time, c.arrival_time));
+
+        let mut arrival_order: Vec<usize> = (0..connections.len()).collect();
+        arrival_order
+            .sort_by_key(|&i| (connections[i].arrival_time, connections[i].departure_time));
+
+        let mut tree = KdTree::new();
+        stops.iter().for_each(|(&id, s)| {
+            tree.add(&to_unit(s.lat, s.lon), id.0);
+        });
+
+        let transfers = adapter.transfers()?;
+        let reverse_transfers = transfers
+            .values()
+            .flatten()
+            .cloned()
+            .into_group_map_by(|t| t.to_stop_id);
+        let stay_seated = adapter.stay_seated()?;
+        let reverse_stay_seated = stay_seated
+            .iter()
+            .cloned()
+            .into_group_map_by(|s| s.from_trip_id);
+        let stay_seated = stay_seated.into_iter().into_group_map_by(|s| s.to_trip_id);
+        let calendar = adapter.calendar()?;
+        let search = SearchIndex::new(&stops);
+
+        Ok(Self {
+            tree,
+            stops,
+            connections,
+            arrival_order,
+            services: services.values,
+            platforms: platforms.values,
+            termini,
+            transfers,
+            reverse_transfers,
+            stay_seated,
+            reverse_stay_seated,
+            calendar,
+            search,
+        })
+    }
+
+    pub fn query(
+        &self,
+        origin: &Origin,
+        date: NaiveDate,
+        departure_time: NaiveTime,
+        options: &QueryOptions,
+    ) -> Vec<ArrivalTime> {
+        let departure_date_time = NaiveDateTime::new(date, departure_time);
+        let access = self.access_stops(origin, options);
+        let mut csa = CsaState::new();
+        self.scan(&mut csa, &access, date, departure_time, options);
+
+        csa.earliest_arrivals()
+            .map(|label| {
+                let stop = self.stop(label.stop_id);
+                let arrival = label.time - departure_date_time;
+
+                let location = geo_types::Point::new(stop.lon, stop.lat);
+                ArrivalTime {
+                    stop_id: label.stop_id,
+                    stop_name: stop.name.clone(),
+                    arrival_time: arrival.num_seconds(),
+                    changes: label.changes(),
+                    arrived_by: match label.via {
+                        JourneyPointer::Connection { exit, .. } => {
+                            Some(self.service(&self.connections[exit]).clone())
+                        }
+                        _ => None,
+                    },
+                    geometry: location,
+                }
+            })
+            .collect()
+    }
+
+    /// Stops within the access radius of the origin, with the time taken to get to
+    /// each. A stop is its own only access stop, reached after just the access penalty.
+    fn access_stops(&self, origin: &Origin, options: &QueryOptions) -> Vec<(StopId, TimeDelta)> {
+        let (lat, lon) = match *origin {
+            Origin::Point { lat, lon } => (lat, lon),
+            Origin::Stop(stop_id) => return vec![(stop_id, options.access_penalty)],
+        };
+
+        self.stops_within_radius(lat, lon, options.access_radius)
+            .map(|(stop_id, distance)| {
+                let seconds = distance * options.detour_factor / options.access_speed;
+                (
+                    stop_id,
+                    TimeDelta::seconds(seconds as i64) + options.access_penalty,
+                )
+            })
+            .collect()
+    }
+
+    /// The stop with the given CRS code or TIPLOC, ignoring case.
+    pub fn stop_by_code(&self, code: &str) -> Option<StopId> {
+        self.stops
+            .iter()
+            .filter(|(_, stop)| stop.codes.iter().any(|c| c.eq_ignore_ascii_case(code)))
+            .map(|(&id, _)| id)
+            .min_by_key(|id| id.0)
+    }
+
+    pub fn has_stop(&self, stop_id: StopId) -> bool {
+        self.stops.contains_key(&stop_id)
+    }
+
+    /// Where the origin is, as a point.
+    pub fn origin_point(&self, origin: &Origin) -> geo_types::Point<f64> {
+        match *origin {
+            Origin::Point { lat, lon } => geo_types::Point::new(lon, lat),
+            Origin::Stop(stop_id) => {
+                let stop = self.stop(stop_id);
+                geo_types::Point::new(stop.lon, stop.lat)
+            }
+        }
+    }
+
+    /// Runs an earliest-arrival scan leaving the access stops at `departure_time`,
+    /// keeping for each stop the Pareto set of arrival time and number of trips used.
+    /// Trips are identified by their service day, so the scan carries on past
+    /// midnight and picks up trips that started the previous day.
+    ///
+    /// Labels already in `csa` are kept, so repeated scans with decreasing departure
+    /// times only ever improve on the previous results.
+    fn scan(
+        &self,
+        csa: &mut CsaState,
+        access: &[(StopId, TimeDelta)],
+        date: NaiveDate,
+        departure_time: NaiveTime,
+        options: &QueryOptions,
+    ) {
+        let departure_date_time = NaiveDateTime::new(date, departure_time);
+        let max_trips = options.max_trips();
+
+        for &(stop_id, walk) in access {
+            let time = departure_date_time + walk;
+            let Some(label) = csa.update_arrival(
+                stop_id,
+                time,
+                time,
+                ChangeGroup::OnFoot,
+                0,
+                JourneyPointer::Access { walk },
+            ) else {
+                continue;
+            };
+
+            for transfer in self.get_transfers(stop_id, time, options) {
+                let new_arrival = time + transfer.transfer_time;
+                csa.update_arrival(
+                    transfer.to_stop_id,
+                    new_arrival,
+                    new_arrival,
+                    ChangeGroup::OnFoot,
+                    0,
+                    JourneyPointer::Transfer { from: label },
+                );
+            }
+        }
+
+        let horizon = departure_date_time + options.horizon;
+        for (idx, c, service_date) in
+            self.connections_departing_between(departure_date_time, horizon)
+        {
+            if !options.allows(self.service(c)) || !self.calendar.runs_on(c.trip_id, service_date) {
+                continue;
+            }
+
+            // Boarding here is only worthwhile if it uses fewer trips than staying on
+            // from an earlier stop
+            let trip_key = (c.trip_id, service_date);
+            let departure = c.departure_date_time(service_date);
+            let from = c
+                .can_board
+                .then(|| {
+                    csa.boarding_label(c.from_stop_id, |label| {
+                        self.ready_to_board(label, c, departure)
+                    })
+                })
+                .flatten();
+            if let Some(from) = from {
+                let trips = csa.label(from).trips + 1;
+                let fewer_trips = csa
+                    .boarded_trip(trip_key, idx)
+                    .is_none_or(|trip| trip.trips > trips);
+
+                if trips <= max_trips && fewer_trips {
+                    csa.board_trip(
+                        trip_key,
+                        BoardedTrip {
+                            trips,
+                            enter: idx,
+                            boarded_from: Boarding::Label(from),
+                        },
+                    );
+                }
+            }
+
+            // Staying on board where trips join or divide doesn't count as a change
+            if let Some(ride) = self.stay_seated_ride(csa, c, service_date) {
+                let trips = csa.ride(ride).trips;
+                if csa
+                    .boarded_trip(trip_key, idx)
+                    .is_none_or(|trip| trip.trips > trips)
+                {
+                    csa.board_trip(
+                        trip_key,
+                        BoardedTrip {
+                            trips,
+                            enter: idx,
+                            boarded_from: Boarding::StaySeated(ride),
+                        },
+                    );
+                }
+            }
+
+            let Some(trip) = csa.boarded_trip(trip_key, idx) else {
+                continue;
+            };
+
+            let joins_or_divides = self
+                .reverse_stay_seated
+                .get(&c.trip_id)
+                .is_some_and(|links| links.iter().any(|l| l.stop_id == c.to_stop_id));
+            if joins_or_divides {
+                csa.ride_through(
+                    trip_key,
+                    c.to_stop_id,
+                    Ride {
+                        service_date,
+                        enter: trip.enter,
+                        exit: idx,
+                        trips: trip.trips,
+                        boarded_from: trip.boarded_from,
+                    },
+                );
+            }
+
+            // Passengers stay on through stops where they may not get off
+            if !c.can_alight {
+                continue;
+            }
+
+            let arrival = c.arrival_date_time(service_date);
+            let Some(label) = csa.update_arrival(
+                c.to_stop_id,
+                arrival,
+                arrival + self.stop(c.to_stop_id).shortest_change_time(),
+                ChangeGroup::Operator(self.service(c).operator.clone()),
+                trip.trips,
+                JourneyPointer::Connection {
+                    service_date,
+                    enter: trip.enter,
+                    exit: idx,
+                    boarded_from: trip.boarded_from,
+                },
+            ) else {
+                continue;
+            };
+
+            for transfer in self.get_transfers(c.to_stop_id, arrival, options) {
+                let new_arrival = arrival + transfer.transfer_time;
+                csa.update_arrival(
+                    transfer.to_stop_id,
+                    new_arrival,
+                    new_arrival,
+                    ChangeGroup::OnFoot,
+                    trip.trips,
+                    JourneyPointer::Transfer { from: label },
+                );
+            }
+        }
+    }
+
+    /// Whether connection `c`, departing at `departure`, can be boarded from `label`.
+    /// Arriving by train means allowing the interchange time between the operators of
+    /// the two trips, whereas arriving on foot means being ready straight away.
+    fn ready_to_board(
+        &self,
+        label: &Label,
+        c: &StoredConnection,
+        departure: NaiveDateTime,
+    ) -> bool {
+        let JourneyPointer::Connection {
+            service_date, exit, ..
+        } = label.via
+        else {
+            return label.time <= departure;
+        };
+
+        let arrived_by = &self.connections[exit];
+        let change_time = self.stop(c.from_stop_id).change_time(
+            self.service(arrived_by).operator.as_deref(),
+            self.service(c).operator.as_deref(),
+        );
+        arrived_by.arrival_date_time(service_date) + change_time <= departure
+    }
+
+    /// The ride, with the fewest trips, from which passengers can stay on board for
+    /// connection `c` of a trip running on `service_date`.
+    fn stay_seated_ride(
+        &self,
+        csa: &CsaState,
+        c: &StoredConnection,
+        service_date: NaiveDate,
+    ) -> Option<usize> {
+        self.stay_seated
+            .get(&c.trip_id)?
+            .iter()
+            .filter(|s| s.stop_id == c.from_stop_id)
+            .filter_map(|s| {
+                let from_date = service_date - TimeDelta::days(s.day_offset);
+                if !s.applies(from_date) {
+                    return None;
+                }
+                csa.ride_index((s.from_trip_id, from_date), s.stop_id)
+            })
+            .min_by_key(|&ride| csa.ride(ride).trips)
+    }
+
+    fn service(&self, c: &StoredConnection) -> &ServiceInfo {
+        &self.services[c.service as usize]
+    }
+
+    fn platform(&self, platform: Option<u32>) -> Option<String> {
+        platform.map(|p| self.platforms[p as usize].clone())
+    }
+
+    /// Transfers from `stop` that can be started at `departure`.
+    fn get_transfers(
+        &self,
+        stop: StopId,
+        departure: NaiveDateTime,
+        options: &QueryOptions,
+    ) -> impl Iterator<Item = &Transfer> {
+        let transfers = match self.transfers.get(&stop) {
+            Some(transfers) => transfers.iter(),
+            None => [].iter(),
+        };
+        transfers.filter(move |t| self.applies_at(t, departure, options))
+    }
+
+    /// Whether `transfer` can be started at `departure`, and no transfer between the
+    /// same stops with a better priority can be then instead.
+    fn applies_at(
+        &self,
+        transfer: &Transfer,
+        departure: NaiveDateTime,
+        options: &QueryOptions,
+    ) -> bool {
+        let outranked = || {
+            self.transfers[&transfer.from_stop_id]
+                .iter()
+                .any(|other| transfer.outranked_by(other) && other.usable_at(departure, options))
+        };
+
+        transfer.usable_at(departure, options) && (transfer.priority.is_none() || !outranked())
+    }
+
+    /// Connections departing between `start` and `end` on every service day that has
+    /// any, in order of departure, with their indices and service days.
+    fn connections_departing_between(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> impl Iterator<Item = (usize, &StoredConnection, NaiveDate)> {
+        let latest = self.connections.last().map_or(0, |c| c.departure_time);
+
+        self.service_days(start, end, latest)
+            .map(move |date| {
+                let (from, to) = seconds_into_service_day(date, start, end);
+                let first = self
+                    .connections
+                    .partition_point(|c| i64::from(c.departure_time) < from);
+                let last = self
+                    .connections
+                    .partition_point(|c| i64::from(c.departure_time) <= to);
+
+                (first..last).map(move |i| (i, &self.connections[i], date))
+            })
+            .kmerge_by(|(_, a, a_date), (_, b, b_date)| {
+                a.departure_date_time(*a_date) < b.departure_date_time(*b_date)
+            })
+    }
+
+    /// Service days whose trips may have a connection between `start` and `end`, given
+    /// that no connection is later than `latest` seconds into its service day.
+    fn service_days(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        latest: u32,
+    ) -> impl Iterator<Item = NaiveDate> + use<> {
+        let first_day = start.date() - TimeDelta::days((latest / SECONDS_PER_DAY).into());
+        first_day.iter_days().take_while(move |&d| d <= end.date())
+    }
+
+    fn stops_within_radius(
+        &self,
+        lat: f64,
+        lon: f64,
+        distance: f64,
+    ) -> impl Iterator<Item = (StopId, f64)> {
+        self.tree
+            .within::<SquaredEuclidean>(&to_unit(lat, lon), meters_to_chord2(distance))
+            .into_iter()
+            .map(|x| (StopId(x.item), chord2_to_meters(x.distance)))
+    }
+
+    fn stop(&self, id: StopId) -> &Stop {
+        &self.stops[&id]
+    }
+}
+
+/// The range from `start` to `end` as seconds since the start of the service day `date`.
+fn seconds_into_service_day(
+    date: NaiveDate,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> (i64, i64) {
+    let midnight = NaiveDateTime::new(date, NaiveTime::MIN);
+    (
+        (start - midnight).num_seconds(),
+        (end - midnight).num_seconds(),
+    )
+}
+
+/// Values stored once each, in the order first seen, and referred to by index.
+struct Interner<T> {
+    indices: HashMap<T, u32>,
+    values: Vec<T>,
+}
+
+impl<T: Clone + Eq + Hash> Interner<T> {
+    fn new() -> Self {
+        Self {
+            indices: HashMap::new(),
+            values: vec![],
+        }
+    }
+
+    fn intern(&mut self, value: T) -> u32 {
+        *self.indices.entry(value).or_insert_with_key(|value| {
+            self.values.push(value.clone());
+            (self.values.len() - 1) as u32
+        })
+    }
+}
+
+pub(crate) const R_EARTH_M: f64 = 6_371_008.8;
+
+pub(crate) fn to_unit(lat_deg: f64, lon_deg: f64) -> [f64; 3] {
+    let (lat, lon) = (lat_deg.to_radians(), lon_deg.to_radians());
+    let (clat, clon, slat, slon) = (lat.cos(), lon.cos(), lat.sin(), lon.sin());
+    [clat * clon, clat * slon, slat]
+}
+
+#[inline]
+pub(crate) fn chord2_to_meters(chord2: f64) -> f64 {
+    let c = chord2.sqrt();
+    let theta = 2.0 * (c / 2.0).asin();
+    R_EARTH_M * theta
+}
+
+#[inline]
+pub(crate) fn meters_to_chord2(d_m: f64) -> f64 {
+    // numerically stable for small d
+    let half = d_m / (2.0 * R_EARTH_M);
+    4.0 * half.sin().powi(2)
+}
+
+pub fn to_feature_collection<T: Serialize>(items: &[T]) -> anyhow::Result<FeatureCollection> {
+    let features = items
+        .iter()
+        .map(geojson::ser::to_feature)
+        .collect::<Result<Vec<Feature>, geojson::Error>>()?;
+
+    Ok(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_network::{TestNetwork, date, time};
+
+    fn validity(start: &str, end: &str) -> Validity {
+        Validity {
+            start_time: time(start),
+            end_time: time(end),
+            start_date: None,
+            end_date: None,
+            days_run: None,
+        }
+    }
+
+    fn on(date: NaiveDate, hh_mm: &str) -> NaiveDateTime {
+        NaiveDateTime::new(date, time(hh_mm))
+    }
+
+    #[test]
+    fn rejects_horizons_longer_than_the_maximum() {
+        let horizon = |horizon| QueryOptions {
+            horizon,
+            ..QueryOptions::default()
+        };
+
+        assert!(horizon(MAX_HORIZON).validate().is_ok());
+        assert!(
+            horizon(MAX_HORIZON + TimeDelta::minutes(1))
+                .validate()
+                .is_err()
+        );
+        assert!(horizon(TimeDelta::zero()).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_departure_windows_longer_than_the_maximum_horizon() {
+        let network = TestNetwork::new(2)
+            .trip(0, &[(0, "09:00"), (1, "10:00")])
+            .build();
+        let departures = |window| {
+            network.departures(
+                StopId(0),
+                date(),
+                time("08:00"),
+                window,
+                &QueryOptions::default(),
+            )
+        };
+
+        assert_eq!(
+            departures(MAX_HORIZON).unwrap().unwrap().departures.len(),
+            1
+        );
+        assert!(departures(MAX_HORIZON + TimeDelta::minutes(1)).is_err());
+    }
+
+    #[test]
+    fn keeps_connections_of_a_trip_in_order_when_they_leave_at_the_same_time() {
+        let calls: Vec<(u64, &str)> = (0..40)
+            .map(|stop| (stop, if stop < 39 { "09:00" } else { "09:10" }))
+            .collect();
+        let network = TestNetwork::new(40)
+            .trip(1, &[(39, "08:00"), (0, "09:00")])
+            .trip(0, &calls)
+            .build();
+
+        let stops: Vec<u64> = network
+            .connections
+            .iter()
+            .filter(|c| c.trip_id == TripId(0))
+            .map(|c| c.from_stop_id.0)
+            .collect();
+        assert_eq!(stops, (0..39).collect::<Vec<_>>());
+
+        let arrival_stops: Vec<u64> = network
+            .arrival_order
+            .iter()
+            .map(|&i| &network.connections[i])
+            .filter(|c| c.trip_id == TripId(0))
+            .map(|c| c.to_stop_id.0)
+            .collect();
+        assert_eq!(arrival_stops, (1..40).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn validity_contains_times_within_its_window() {
+        let daytime = validity("07:00", "19:00");
+
+        assert!(daytime.contains(on(date(), "07:00")));
+        assert!(daytime.contains(on(date(), "19:00")));
+        assert!(!daytime.contains(on(date(), "06:59")));
+        assert!(!daytime.contains(on(date(), "19:01")));
+    }
+
+    #[test]
+    fn validity_runs_past_midnight_on_the_date_it_starts() {
+        // Mondays only, and `date()` is a Monday
+        let monday_night = Validity {
+            days_run: Some([true, false, false, false, false, false, false]),
+            ..validity("22:00", "02:00")
+        };
+        let tuesday = date() + TimeDelta::days(1);
+
+        assert!(monday_night.contains(on(date(), "23:00")));
+        assert!(monday_night.contains(on(tuesday, "01:30")));
+        assert!(!monday_night.contains(on(date(), "01:30")));
+        assert!(!monday_night.contains(on(tuesday, "23:00")));
+        assert!(!monday_night.contains(on(date(), "03:00")));
+    }
+
+    #[test]
+    fn validity_only_contains_dates_in_its_range() {
+        let this_week = Validity {
+            start_date: Some(date()),
+            end_date: Some(date() + TimeDelta::days(6)),
+            ..validity("22:00", "02:00")
+        };
+
+        assert!(!this_week.contains(on(date(), "01:00")));
+        assert!(this_week.contains(on(date() + TimeDelta::days(7), "01:00")));
+        assert!(!this_week.contains(on(date() + TimeDelta::days(7), "23:00")));
+    }
+
+    #[test]
+    fn uses_the_best_priority_transfer_that_can_be_started_at_the_time() {
+        let link = |mode, minutes, validity, priority| Transfer {
+            from_stop_id: StopId(0),
+            to_stop_id: StopId(1),
+            transfer_time: TimeDelta::minutes(minutes),
+            validity,
+            mode,
+            priority: Some(priority),
+        };
+        let network = TestNetwork::new(2)
+            .transfer(link(
+                TransferMode::Tube,
+                10,
+                Some(validity("05:30", "00:30")),
+                1,
+            ))
+            .transfer(link(TransferMode::Walk, 30, None, 2))
+            .build();
+        let modes = |hh_mm, options: &QueryOptions| -> Vec<TransferMode> {
+            network
+                .get_transfers(StopId(0), on(date(), hh_mm), options)
+                .map(|t| t.mode)
+                .collect()
+        };
+
+        let options = QueryOptions::default();
+        assert_eq!(modes("12:00", &options), [TransferMode::Tube]);
+        assert_eq!(modes("00:15", &options), [TransferMode::Tube]);
+        assert_eq!(modes("03:00", &options), [TransferMode::Walk]);
+
+        let walk_only = QueryOptions {
+            transfer_modes: HashSet::from([TransferMode::Walk]),
+            ..QueryOptions::default()
+        };
+        assert_eq!(modes("12:00", &walk_only), [TransferMode::Walk]);
+    }
+
+    #[test]
+    fn transfers_without_a_priority_never_compete() {
+        let walk = |minutes, priority| Transfer {
+            from_stop_id: StopId(0),
+            to_stop_id: StopId(1),
+            transfer_time: TimeDelta::minutes(minutes),
+            validity: None,
+            mode: TransferMode::Walk,
+            priority,
+        };
+        let network = TestNetwork::new(2)
+            .transfer(walk(5, Some(1)))
+            .transfer(walk(8, None))
+            .transfer(walk(10, Some(2)))
+            .build();
+
+        let times: Vec<i64> = network
+            .get_transfers(StopId(0), on(date(), "12:00"), &QueryOptions::default())
+            .map(|t| t.transfer_time.num_minutes())
+            .collect();
+        assert_eq!(times, [5, 8]);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use geojson::ser::serialize_geometry;
+use serde::Serialize;
+
+use super::{CsaState, Origin, QueryOptions, StopId, Transfer, TransportNetwork};
+
+/// One Pareto-optimal journey: no other journey leaves later and arrives earlier.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileEntry {
+    pub departure_time: NaiveDateTime,
+    pub arrival_time: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopProfile {
+    pub stop_id: StopId,
+    pub stop_name: String,
+    pub best_travel_time: i64,
+    pub median_travel_time: i64,
+    pub worst_travel_time: i64,
+    pub journeys: Vec<ProfileEntry>,
+    #[serde(serialize_with = "serialize_geometry")]
+    pub geometry: geo_types::Point<f64>,
+}
+
+impl TransportNetwork {
+    /// Computes, for every reachable stop, the Pareto set of (departure, arrival) pairs
+    /// for journeys leaving `origin` between `window_start` and `window_end`.
+    ///
+    /// Candidate departures are scanned latest first and the state is reused between
+    /// scans, since anything reachable by leaving later is also reachable by leaving
+    /// earlier and waiting.
+    pub fn profile(
+        &self,
+        origin: &Origin,
+        date: NaiveDate,
+        window_start: NaiveTime,
+        window_end: NaiveTime,
+        options: &QueryOptions,
+    ) -> anyhow::Result<Vec<StopProfile>> {
+        ensure!(
+            window_start <= window_end,
+            "departure window ends ({window_end}) before it starts ({window_start})"
+        );
+
+        let access = self.access_stops(origin, options);
+        let offsets = self.walking_offsets(&access, options);
+        let departures = self.origin_departures(&offsets, date, window_start, window_end);
+
+        let mut csa = CsaState::new();
+        let mut journeys: HashMap<StopId, Vec<ProfileEntry>> = HashMap::new();
+
+        for &departure_time in departures.iter().rev() {
+            self.scan(&mut csa, &access, date, departure_time, options);
+
+            let departure = NaiveDateTime::new(date, departure_time);
+            for label in csa.earliest_arrivals() {
+                let entries = journeys.entry(label.stop_id).or_default();
+                if entries.last().is_none_or(|e| e.arrival_time > label.time) {
+                    entries.push(ProfileEntry {
+                        departure_time: departure,
+                        arrival_time: label.time,
+                    });
+                }
+            }
+        }
+
+        let window_start = NaiveDateTime::new(date, window_start);
+        let window_end = NaiveDateTime::new(date, window_end);
+
+        Ok(journeys
+            .into_iter()
+            .map(|(stop_id, mut entries)| {
+                entries.reverse();
+                let stop = self.stop(stop_id);
+                let walk = offsets.get(&stop_id).copied();
+                let (best, median, worst) =
+                    travel_time_stats(&entries, walk, window_start, window_end);
+
+                StopProfile {
+                    stop_id,
+                    stop_name: stop.name.clone(),
+                    best_travel_time: best,
+                    median_travel_time: median,
+                    worst_travel_time: worst,
+                    journeys: entries,
+                    geometry: geo_types::Point::new(stop.lon, stop.lat),
+                }
+            })
+            .collect())
+    }
+
+    /// Shortest time to reach each stop from the origin on foot alone, at any time.
+    fn walking_offsets(
+        &self,
+        access: &[(StopId, TimeDelta)],
+        options: &QueryOptions,
+    ) -> HashMap<StopId, TimeDelta> {
+        let mut offsets: HashMap<StopId, TimeDelta> = HashMap::new();
+        for &(stop_id, walk) in access {
+            let mut add_offset = |stop_id, offset| {
+                offsets
+                    .entry(stop_id)
+                    .and_modify(|o: &mut TimeDelta| *o = (*o).min(offset))
+                    .or_insert(offset);
+            };
+
+            add_offset(stop_id, walk);
+            // Only transfers that can be used at any time, as these offsets apply
+            // throughout the window
+            let transfers = self.transfers.get(&stop_id).into_iter().flatten();
+            let allowed = |t: &Transfer| options.transfer_modes.contains(&t.mode);
+            let usable = |t: &&Transfer| {
+                t.validity.is_none()
+                    && allowed(t)
+                    && !transfers
+                        .clone()
+                        .any(|other| allowed(other) && t.outranked_by(other))
+            };
+            for transfer in transfers.clone().filter(usable) {
+                add_offset(transfer.to_stop_id, walk + transfer.transfer_time);
+            }
+        }
+
+        offsets
+    }
+
+    /// Every time within the window at which leaving the origin lets you catch a
+    /// connection without waiting, plus the window edges, sorted ascending.
+    fn origin_departures(
+        &self,
+        offsets: &HashMap<StopId, TimeDelta>,
+        date: NaiveDate,
+        window_start: NaiveTime,
+        window_end: NaiveTime,
+    ) -> Vec<NaiveTime> {
+        let start = NaiveDateTime::new(date, window_start);
+        let end = NaiveDateTime::new(date, window_end);
+        let max_offset = offsets.values().copied().max().unwrap_or_default();
+
+        let mut departures: Vec<NaiveTime> = self
+            .connections_departing_between(start, end + max_offset)
+            .filter(|(_, c, service_date)| {
+                c.can_board && self.calendar.runs_on(c.trip_id, *service_date)
+            })
+            .filter_map(|(_, c, service_date)| {
+                let departure =
+                    c.departure_date_time(service_date) - *offsets.get(&c.from_stop_id)?;
+                (start..=end)
+                    .contains(&departure)
+                    .then_some(departure.time())
+            })
+            .chain([window_start, window_end])
+            .collect();
+
+        departures.sort_unstable();
+        departures.dedup();
+        departures
+    }
+}
+
+/// Best, median and worst travel time in seconds. The best is taken over the journeys
+/// themselves; median and worst over each minute of the window from which a journey
+/// can still be caught, including the wait for it. Stops within walking distance of
+/// the origin can be walked to at any time without waiting.
+fn travel_time_stats(
+    entries: &[ProfileEntry],
+    walk: Option<TimeDelta>,
+    window_start: NaiveDateTime,
+    window_end: NaiveDateTime,
+) -> (i64, i64, i64) {
+    let walk = walk.map(|w| w.num_seconds());
+    let best = entries
+        .iter()
+        .map(|e| (e.arrival_time - e.departure_time).num_seconds())
+        .chain(walk)
+        .min()
+        .unwrap_or_default();
+
+    let mut samples = vec![];
+    let mut t = window_start;
+    while t <= window_end {
+        let next = entries.partition_point(|e| e.departure_time < t);
+        let by_journey = entries
+            .get(next)
+            .map(|entry| (entry.arrival_time - t).num_seconds());
+        if let Some(travel_time) = by_journey.into_iter().chain(walk).min() {
+            samples.push(travel_time);
+        }
+        t += TimeDelta::minutes(1);
+    }
+
+    samples.sort_unstable();
+    let median = samples.get(samples.len() / 2).copied().unwrap_or(best);
+    let worst = samples.last().copied().unwrap_or(best);
+
+    (best, median, worst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::test_network::{TestNetwork, date, time};
+
+    fn reached(profile: &[StopProfile]) -> Vec<u64> {
+        let mut stops: Vec<u64> = profile.iter().map(|p| p.stop_id.index()).collect();
+        stops.sort_unstable();
+        stops
+    }
+
+    #[test]
+    fn never_rides_a_trip_back_to_before_where_it_was_boarded() {
+        let network = TestNetwork::new(4)
+            .trip(0, &[(0, "08:50"), (1, "09:00"), (2, "09:10"), (3, "09:20")])
+            .build();
+        let origin = Origin::Stop(StopId::new(2));
+        let options = QueryOptions::default();
+
+        let profile = network
+            .profile(&origin, date(), time("08:40"), time("09:11"), &options)
+            .unwrap();
+
+        assert_eq!(reached(&profile), [2, 3]);
+        let s3 = profile
+            .iter()
+            .find(|p| p.stop_id == StopId::new(3))
+            .unwrap();
+        assert_eq!(s3.journeys.len(), 1);
+        assert_eq!(s3.journeys[0].departure_time.time(), time("09:10"));
+    }
+
+    #[test]
+    fn keeps_only_journeys_that_leave_later_or_arrive_earlier() {
+        let network = TestNetwork::new(2)
+            .trip(0, &[(0, "09:00"), (1, "10:00")])
+            .trip(1, &[(0, "09:15"), (1, "09:45")])
+            .trip(2, &[(0, "09:30"), (1, "10:30")])
+            .build();
+        let origin = Origin::Stop(StopId::new(0));
+        let options = QueryOptions::default();
+
+        let profile = network
+            .profile(&origin, date(), time("08:30"), time("09:30"), &options)
+            .unwrap();
+
+        let s1 = profile
+            .iter()
+            .find(|p| p.stop_id == StopId::new(1))
+            .unwrap();
+        let journeys: Vec<(NaiveTime, NaiveTime)> = s1
+            .journeys
+            .iter()
+            .map(|j| (j.departure_time.time(), j.arrival_time.time()))
+            .collect();
+        assert_eq!(
+            journeys,
+            [
+                (time("09:15"), time("09:45")),
+                (time("09:30"), time("10:30"))
+            ]
+        );
+        assert_eq!(s1.best_travel_time, 30 * 60);
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use geojson::ser::serialize_geometry;
+use serde::Serialize;
+
+use itertools::Itertools;
+
+use super::{
+    Origin, QueryOptions, StopId, StoredConnection, Transfer, TransportNetwork, TripId,
+    seconds_into_service_day, state::ChangeGroup,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepartureTime {
+    pub stop_id: StopId,
+    pub stop_name: String,
+    /// Seconds before the deadline at which you must leave this stop.
+    pub departure_time: i64,
+    /// Number of changes between trips on the latest-leaving journey.
+    pub changes: u32,
+    #[serde(serialize_with = "serialize_geometry")]
+    pub geometry: geo_types::Point<f64>,
+}
+
+impl TransportNetwork {
+    /// Finds the latest time you can leave each stop and still reach `origin`
+    /// by `arrival_time` on `date`, scanning connections backwards from the deadline.
+    pub fn query_arrive_by(
+        &self,
+        origin: &Origin,
+        date: NaiveDate,
+        arrival_time: NaiveTime,
+        options: &QueryOptions,
+    ) -> Vec<DepartureTime> {
+        let deadline = NaiveDateTime::new(date, arrival_time);
+        let max_trips = options.max_trips();
+        let mut csa = ReverseCsaState::new();
+
+        for (stop_id, walk) in self.access_stops(origin, options) {
+            let time = deadline - walk;
+            if !csa.update_departure(stop_id, time, time, ChangeGroup::OnFoot, 0, None) {
+                continue;
+            }
+
+            for transfer in self.get_reverse_transfers(stop_id, time, options) {
+                let new_departure = time - transfer.transfer_time;
+                csa.update_departure(
+                    transfer.from_stop_id,
+                    new_departure,
+                    new_departure,
+                    ChangeGroup::OnFoot,
+                    0,
+                    None,
+                );
+            }
+        }
+
+        let horizon = deadline - options.horizon;
+        for (idx, c, service_date) in self.connections_arriving_between(horizon, deadline) {
+            if !options.allows(self.service(c)) || !self.calendar.runs_on(c.trip_id, service_date) {
+                continue;
+            }
+
+            let trip_key = (c.trip_id, service_date);
+            let arrival = c.arrival_date_time(service_date);
+            let trips = c
+                .can_alight
+                .then(|| {
+                    csa.alighting_trips(c.to_stop_id, |change| {
+                        self.ready_to_change(change, c, arrival)
+                    })
+                })
+                .flatten();
+            if let Some(trips) = trips {
+                let trips = trips + 1;
+                let fewer_trips = csa.boarded_trips.get(&trip_key).is_none_or(|&t| t > trips);
+
+                if trips <= max_trips && fewer_trips {
+                    csa.boarded_trips.insert(trip_key, trips);
+                }
+            }
+
+            if let Some(trips) = self.stay_seated_trips(&csa, c, service_date) {
+                let fewer_trips = csa.boarded_trips.get(&trip_key).is_none_or(|&t| t > trips);
+                if fewer_trips {
+                    csa.boarded_trips.insert(trip_key, trips);
+                }
+            }
+
+            let Some(&trips) = csa.boarded_trips.get(&trip_key) else {
+                continue;
+            };
+
+            if !c.can_board {
+                continue;
+            }
+
+            let departure = c.departure_date_time(service_date);
+            let later_departure = csa.update_departure(
+                c.from_stop_id,
+                departure,
+                departure - self.stop(c.from_stop_id).shortest_change_time(),
+                ChangeGroup::Operator(self.service(c).operator.clone()),
+                trips,
+                Some((idx, departure)),
+            );
+
+            if later_departure {
+                for transfer in self.get_reverse_transfers(c.from_stop_id, departure, options) {
+                    let new_departure = departure - transfer.transfer_time;
+                    csa.update_departure(
+                        transfer.from_stop_id,
+                        new_departure,
+                        new_departure,
+                        ChangeGroup::OnFoot,
+                        trips,
+                        None,
+                    );
+                }
+            }
+        }
+
+        csa.departures
+            .keys()
+            .filter_map(|&k| Some((k, csa.latest_departure(k)?)))
+            .map(|(k, (time, trips))| {
+                let stop = self.stop(k);
+                let departure = deadline - time;
+
+                let location = geo_types::Point::new(stop.lon, stop.lat);
+                DepartureTime {
+                    stop_id: k,
+                    stop_name: stop.name.clone(),
+                    departure_time: departure.num_seconds(),
+                    changes: trips.saturating_sub(1),
+                    geometry: location,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether arriving at `arrival` on connection `c` leaves time to make `change`.
+    /// Changing trains means allowing the interchange time between the operators of
+    /// the two trips, whereas leaving on foot can happen straight away.
+    fn ready_to_change(
+        &self,
+        change: &Change,
+        c: &StoredConnection,
+        arrival: NaiveDateTime,
+    ) -> bool {
+        let Some((idx, departure)) = change.departed_on else {
+            return change.time >= arrival;
+        };
+
+        let change_time = self.stop(c.to_stop_id).change_time(
+            self.service(c).operator.as_deref(),
+            self.service(&self.connections[idx]).operator.as_deref(),
+        );
+        arrival + change_time <= departure
+    }
+
+    /// The fewest trips with which the target can be reached by staying on board at
+    /// the end of connection `c`, onto a trip that joins or divides from this one.
+    fn stay_seated_trips(
+        &self,
+        csa: &ReverseCsaState,
+        c: &StoredConnection,
+        service_date: NaiveDate,
+    ) -> Option<u32> {
+        self.reverse_stay_seated
+            .get(&c.trip_id)?
+            .iter()
+            .filter(|s| s.stop_id == c.to_stop_id && s.applies(service_date))
+            .filter_map(|s| {
+                let onward = (s.to_trip_id, s.onward_date(service_date));
+                csa.boarded_trips.get(&onward).copied()
+            })
+            .min()
+    }
+
+    /// Transfers to `stop` that can be started in time to arrive at `arrival`.
+    fn get_reverse_transfers(
+        &self,
+        stop: StopId,
+        arrival: NaiveDateTime,
+        options: &QueryOptions,
+    ) -> impl Iterator<Item = &Transfer> {
+        let transfers = match self.reverse_transfers.get(&stop) {
+            Some(transfers) => transfers.iter(),
+            None => [].iter(),
+        };
+        transfers.filter(move |t| self.applies_at(t, arrival - t.transfer_time, options))
+    }
+
+    /// Connections arriving between `start` and `end` on every service day that has
+    /// any, latest arrival first, with their indices and service days.
+    fn connections_arriving_between(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> impl Iterator<Item = (usize, &StoredConnection, NaiveDate)> {
+        let latest = self
+            .arrival_order
+            .last()
+            .map_or(0, |&i| self.connections[i].arrival_time);
+
+        self.service_days(start, end, latest)
+            .map(move |date| {
+                let (from, to) = seconds_into_service_day(date, start, end);
+                let first = self
+                    .arrival_order
+                    .partition_point(|&i| i64::from(self.connections[i].arrival_time) < from);
+                let last = self
+                    .arrival_order
+                    .partition_point(|&i| i64::from(self.connections[i].arrival_time) <= to);
+
+                self.arrival_order[first..last]
+                    .iter()
+                    .rev()
+                    .map(move |&i| (i, &self.connections[i], date))
+            })
+            .kmerge_by(|(_, a, a_date), (_, b, b_date)| {
+                a.arrival_date_time(*a_date) > b.arrival_date_time(*b_date)
+            })
+    }
+}
+
+/// Mirror image of `CsaState`: tracks, for each stop, the Pareto set of latest
+/// departure and number of trips that still reaches the target, and the fewest
+/// trips with which each trip reaches it from a later stop.
+#[derive(Debug, Default)]
+struct ReverseCsaState {
+    departures: HashMap<StopId, Vec<(NaiveDateTime, u32)>>,
+    /// Latest times at which you can arrive at each stop on one trip and still change
+    /// onto the onward journey, allowing for the shortest change time.
+    changes: HashMap<StopId, HashMap<ChangeGroup, Vec<Change>>>,
+    boarded_trips: HashMap<(TripId, NaiveDate), u32>,
+}
+
+impl ReverseCsaState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records leaving `stop_id` at `time` using `trips` trips, which a different trip
+    /// must reach by `change_time`, on the connection `departed_on` if any. Returns
+    /// whether the departure was not dominated.
+    pub fn update_departure(
+        &mut self,
+        stop_id: StopId,
+        time: NaiveDateTime,
+        change_time: NaiveDateTime,
+        group: ChangeGroup,
+        trips: u32,
+        departed_on: Option<(usize, NaiveDateTime)>,
+    ) -> bool {
+        // Changes onto trains only compete with others by the same operator, or with
+        // leaving on foot
+        let groups = self.changes.entry(stop_id).or_default();
+        let dominated = [&group, &ChangeGroup::OnFoot]
+            .into_iter()
+            .filter_map(|g| groups.get(g))
+            .flatten()
+            .any(|c| c.time >= change_time && c.trips <= trips);
+        if !dominated {
+            let keep = |c: &Change| c.time > change_time || c.trips < trips;
+            if group == ChangeGroup::OnFoot {
+                groups.values_mut().for_each(|changes| changes.retain(keep));
+            } else if let Some(changes) = groups.get_mut(&group) {
+                changes.retain(keep);
+            }
+            groups.entry(group).or_default().push(Change {
+                time: change_time,
+                trips,
+                departed_on,
+            });
+        }
+
+        insert_label(self.departures.entry(stop_id).or_default(), time, trips)
+    }
+
+    /// The fewest trips with which the target can be reached after arriving at
+    /// `stop_id` on another trip, using the changes `can_change` allows.
+    pub fn alighting_trips(
+        &self,
+        stop_id: StopId,
+        can_change: impl Fn(&Change) -> bool,
+    ) -> Option<u32> {
+        self.changes
+            .get(&stop_id)?
+            .values()
+            .flatten()
+            .filter(|&c| can_change(c))
+            .map(|c| c.trips)
+            .min()
+    }
+
+    /// The latest departure from `stop_id`, using as few trips as possible.
+    pub fn latest_departure(&self, stop_id: StopId) -> Option<(NaiveDateTime, u32)> {
+        self.departures
+            .get(&stop_id)?
+            .iter()
+            .copied()
+            .max_by_key(|&(time, trips)| (time, std::cmp::Reverse(trips)))
+    }
+}
+
+/// A way of continuing from a stop after arriving there on a trip.
+#[derive(Clone, Copy, Debug)]
+struct Change {
+    /// Latest arrival that might still make the change.
+    time: NaiveDateTime,
+    trips: u32,
+    /// The connection left on and its departure time, or `None` if leaving on foot.
+    departed_on: Option<(usize, NaiveDateTime)>,
+}
+
+/// Adds a label to a Pareto set where later times and fewer trips are better,
+/// returning whether it was not dominated.
+fn insert_label(bag: &mut Vec<(NaiveDateTime, u32)>, time: NaiveDateTime, trips: u32) -> bool {
+    if bag.iter().any(|&(t, n)| t >= time && n <= trips) {
+        return false;
+    }
+
+    bag.retain(|&(t, n)| t > time || n < trips);
+    bag.push((time, trips));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::test_network::{TestNetwork, date, time};
+
+    /// Minutes before the deadline to leave each stop, and the changes needed.
+    fn latest_departures(
+        network: &TransportNetwork,
+        options: &QueryOptions,
+    ) -> HashMap<u64, (i64, u32)> {
+        let destination = Origin::Stop(StopId::new(2));
+        network
+            .query_arrive_by(&destination, date(), time("09:40"), options)
+            .into_iter()
+            .map(|d| (d.stop_id.index(), (d.departure_time / 60, d.changes)))
+            .collect()
+    }
+
+    fn network() -> TestNetwork {
+        TestNetwork::new(3)
+            .trip(0, &[(0, "08:00"), (1, "08:30")])
+            .trip(1, &[(0, "08:20"), (1, "08:50")])
+            .trip(2, &[(1, "09:00"), (2, "09:30")])
+    }
+
+    #[test]
+    fn leaves_each_stop_as_late_as_still_arrives_by_the_deadline() {
+        let departures = latest_departures(&network().build(), &QueryOptions::default());
+
+        assert_eq!(
+            departures,
+            HashMap::from([(0, (80, 1)), (1, (40, 0)), (2, (0, 0))])
+        );
+    }
+
+    #[test]
+    fn allows_the_change_time_before_the_onward_trip() {
+        let network = network().change_time(1, 15).build();
+
+        let departures = latest_departures(&network, &QueryOptions::default());
+
+        assert_eq!(departures[&0], (100, 1));
+    }
+
+    #[test]
+    fn leaves_out_stops_that_need_too_many_changes() {
+        let options = QueryOptions {
+            max_transfers: Some(0),
+            ..QueryOptions::default()
+        };
+
+        let departures = latest_departures(&network().build(), &options);
+
+        assert_eq!(departures, HashMap::from([(1, (40, 0)), (2, (0, 0))]));
+    }
+
+    #[test]
+    fn stays_seated_without_a_change_where_trips_join_or_divide() {
+        let network = TestNetwork::new(3)
+            .trip(0, &[(0, "08:00"), (1, "08:30")])
+            .trip(1, &[(1, "08:35"), (2, "09:00")])
+            .stay_seated(0, 1, 1)
+            .change_time(1, 10)
+            .build();
+
+        let departures = latest_departures(&network, &QueryOptions::default());
+
+        assert_eq!(departures[&0], (100, 0));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Stop, StopId, TransportNetwork};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopMatch {
+    pub stop_id: StopId,
+    pub name: String,
+    /// The name, alias or code that matched the query.
+    pub matched: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Every name, alias and code of every stop, normalised for matching.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(super) struct SearchIndex {
+    /// Normalised terms, with their stops and the text they came from.
+    terms: Vec<(String, StopId, String)>,
+}
+
+impl SearchIndex {
+    pub fn new(stops: &HashMap<StopId, Stop>) -> Self {
+        let mut terms: Vec<_> = stops
+            .iter()
+            .flat_map(|(&id, stop)| {
+                std::iter::once(&stop.name)
+                    .chain(&stop.aliases)
+                    .chain(&stop.codes)
+                    .map(move |term| (normalise(term), id, term.clone()))
+            })
+            .filter(|(term, _, _)| !term.is_empty())
+            .collect();
+        terms.sort_unstable_by(|a, b| (&a.0, a.1.0).cmp(&(&b.0, b.1.0)));
+        terms.dedup_by(|a, b| a.0 == b.0 && a.1 == b.1);
+
+        Self { terms }
+    }
+
+    /// Stops matching `query`, best first: exact matches, then prefixes of a term,
+    /// then prefixes of a word in a term, then close misspellings.
+    fn search(&self, query: &str) -> Vec<(StopId, &str)> {
+        let query = normalise(query);
+        if query.is_empty() {
+            return vec![];
+        }
+
+        let max_distance = (query.chars().count() / 4).max(1);
+        let mut best: HashMap<StopId, (usize, &str)> = HashMap::new();
+        for (term, id, original) in &self.terms {
+            let score = if *term == query {
+                0
+            } else if term.starts_with(&query) {
+                1
+            } else if term.split(' ').any(|word| word.starts_with(&query)) {
+                2
+            } else {
+                // Compare against as much of the term as was typed, so that partly
+                // typed names can still be misspelt
+                let prefix: String = term.chars().take(query.chars().count()).collect();
+                let distance = edit_distance(&query, term).min(edit_distance(&query, &prefix));
+                if distance > max_distance {
+                    continue;
+                }
+                3 + distance
+            };
+
+            let entry = best.entry(*id).or_insert((score, original.as_str()));
+            if score < entry.0 {
+                *entry = (score, original.as_str());
+            }
+        }
+
+        let mut matches: Vec<_> = best.into_iter().collect();
+        matches.sort_by_key(|&(id, (score, matched))| (score, matched.len(), matched, id.0));
+        matches
+            .into_iter()
+            .map(|(id, (_, matched))| (id, matched))
+            .collect()
+    }
+}
+
+impl TransportNetwork {
+    /// Up to `limit` stops whose names, aliases, CRS codes or TIPLOCs match `query`,
+    /// ignoring case and punctuation, best first.
+    pub fn search_stops(&self, query: &str, limit: usize) -> Vec<StopMatch> {
+        self.search
+            .search(query)
+            .into_iter()
+            .take(limit)
+            .map(|(stop_id, matched)| {
+                let stop = self.stop(stop_id);
+                StopMatch {
+                    stop_id,
+                    name: stop.name.clone(),
+                    matched: matched.to_owned(),
+                    lat: stop.lat,
+                    lon: stop.lon,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lower case letters and digits, with everything else collapsed into single spaces.
+fn normalise(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn index(names: &[(&str, &str)]) -> SearchIndex {
+        let stops = names
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, crs))| {
+                let mut stop = Stop::new(name.to_owned(), 51.5, -0.1, TimeDelta::minutes(5));
+                stop.codes = vec![crs.to_owned()];
+                (StopId::new(i as u64), stop)
+            })
+            .collect();
+        SearchIndex::new(&stops)
+    }
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("kings cross", "kings cross"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("padington", "paddington"), 1);
+        assert_eq!(edit_distance("", "euston"), 6);
+    }
+
+    #[test]
+    fn ranks_exact_then_prefix_then_word_then_fuzzy_matches() {
+        let index = index(&[
+            ("London Euston", "EUS"),
+            ("Euston Square", "ESQ"),
+            ("Eustn", "XXX"),
+            ("Paddington", "PAD"),
+        ]);
+
+        let matches: Vec<_> = index
+            .search("euston")
+            .into_iter()
+            .map(|(id, _)| id.index())
+            .collect();
+
+        assert_eq!(matches, [1, 0, 2]);
+    }
+
+    #[test]
+    fn matches_codes_ignoring_case_and_punctuation() {
+        let index = index(&[("London Paddington", "PAD"), ("St. Pancras", "STP")]);
+
+        assert_eq!(index.search("pad"), [(StopId::new(0), "PAD")]);
+        assert_eq!(
+            index.search("st pancras"),
+            [(StopId::new(1), "St. Pancras")]
+        );
+        assert!(index.search("  ").is_empty());
+    }
+
+    #[test]
+    fn tolerates_misspellings_in_proportion_to_query_length() {
+        let index = index(&[("Paddington", "PAD")]);
+
+        assert_eq!(index.search("padingtn").len(), 1);
+        assert!(index.search("pxddxngtxn").is_empty());
+    }
+}
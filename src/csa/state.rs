@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+
+use super::{StopId, TripId};
+
+/// How a label was reached, for reconstructing journeys.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum JourneyPointer {
+    /// Walked directly from the origin.
+    Access { walk: TimeDelta },
+    /// Rode a trip on the service day `service_date`, boarding at connection `enter`
+    /// from label `boarded_from` and alighting after connection `exit`.
+    Connection {
+        service_date: NaiveDate,
+        enter: usize,
+        exit: usize,
+        boarded_from: Boarding,
+    },
+    /// Walked from the stop of label `from`.
+    Transfer { from: usize },
+}
+
+/// Where a trip was boarded from.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Boarding {
+    /// Changed onto the trip at the stop of this label.
+    Label(usize),
+    /// Stayed on board from the ride with this index, where the trips join or divide.
+    StaySeated(usize),
+}
+
+/// Riding a trip on the service day `service_date` from connection `enter` to the
+/// end of connection `exit`, without necessarily getting off.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Ride {
+    pub service_date: NaiveDate,
+    pub enter: usize,
+    pub exit: usize,
+    pub trips: u32,
+    pub boarded_from: Boarding,
+}
+
+/// How a change label reached or leaves its stop. The time needed to change trains
+/// depends on the operators of both trains, so change labels only compete with others
+/// for the same operator, or with labels on foot, which need no time to change.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) enum ChangeGroup {
+    OnFoot,
+    Operator(Option<String>),
+}
+
+/// A way of reaching a stop at a given time using a given number of trips.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Label {
+    pub stop_id: StopId,
+    pub time: NaiveDateTime,
+    pub trips: u32,
+    pub via: JourneyPointer,
+}
+
+impl Label {
+    /// Number of changes between trips, as opposed to trips ridden.
+    pub fn changes(&self) -> u32 {
+        self.trips.saturating_sub(1)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct BoardedTrip {
+    /// Number of trips used up to and including this one.
+    pub trips: u32,
+    /// Index of the connection the trip was boarded at.
+    pub enter: usize,
+    /// Where the trip was boarded from.
+    pub boarded_from: Boarding,
+}
+
+/// State of a multi-criteria scan. Every stop keeps a Pareto set ("bag") of labels,
+/// where a label is only kept if no other label reaches the stop at least as early
+/// with at most as many trips, and for changes arrives the same way.
+#[derive(Debug, Default)]
+pub(super) struct CsaState {
+    /// Every label ever created; bags and journey pointers index into this.
+    labels: Vec<Label>,
+    arrivals: HashMap<StopId, Vec<usize>>,
+    /// Labels for boarding a different trip at each stop. These are later than the
+    /// arrival when the stop was reached by train, to allow for the minimum change
+    /// time, but not when it was reached on foot.
+    changes: HashMap<StopId, HashMap<ChangeGroup, Vec<usize>>>,
+    boarded_trips: HashMap<TripKey, BoardedTrip>,
+    /// Rides through stops where another trip joins or divides, which passengers can
+    /// stay on board from.
+    rides: Vec<Ride>,
+    rides_through: HashMap<(TripKey, StopId), usize>,
+}
+
+/// A trip running on a particular service day.
+pub(super) type TripKey = (TripId, NaiveDate);
+
+impl CsaState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn label(&self, idx: usize) -> &Label {
+        &self.labels[idx]
+    }
+
+    /// Records reaching `stop_id` at `time` having used `trips` trips, ready to board
+    /// another trip at `change_time`. Returns the new arrival label, unless it was
+    /// dominated by an existing one.
+    pub fn update_arrival(
+        &mut self,
+        stop_id: StopId,
+        time: NaiveDateTime,
+        change_time: NaiveDateTime,
+        group: ChangeGroup,
+        trips: u32,
+        via: JourneyPointer,
+    ) -> Option<usize> {
+        let label = Label {
+            stop_id,
+            time,
+            trips,
+            via,
+        };
+
+        let arrival = self.insert(&Bag::Arrivals, label);
+
+        let change_label = Label {
+            time: change_time,
+            ..label
+        };
+        let changes = Bag::Changes(group);
+        match arrival {
+            // Reaching a stop on foot means being ready to board straight away, so
+            // both bags can share a label
+            Some(idx) if change_time == time => self.insert_existing(&changes, idx),
+            _ => {
+                self.insert(&changes, change_label);
+            }
+        }
+
+        arrival
+    }
+
+    /// The label with the fewest trips at `stop_id` from which `can_board` allows
+    /// boarding.
+    pub fn boarding_label(
+        &self,
+        stop_id: StopId,
+        can_board: impl Fn(&Label) -> bool,
+    ) -> Option<usize> {
+        self.changes
+            .get(&stop_id)?
+            .values()
+            .flatten()
+            .copied()
+            .filter(|&i| can_board(&self.labels[i]))
+            .min_by_key(|&i| self.labels[i].trips)
+    }
+
+    pub fn board_trip(&mut self, trip: TripKey, boarded: BoardedTrip) {
+        self.boarded_trips.insert(trip, boarded);
+    }
+
+    /// How `trip` was boarded, if it was at or before connection `idx`. A trip boarded
+    /// by an earlier scan with a later departure time isn't on board yet for the
+    /// connections before it was boarded.
+    pub fn boarded_trip(&self, trip: TripKey, idx: usize) -> Option<BoardedTrip> {
+        self.boarded_trips
+            .get(&trip)
+            .copied()
+            .filter(|trip| trip.enter <= idx)
+    }
+
+    /// Records riding a trip through `stop_id` at the end of `ride`.
+    pub fn ride_through(&mut self, trip: TripKey, stop_id: StopId, ride: Ride) {
+        self.rides_through.insert((trip, stop_id), self.rides.len());
+        self.rides.push(ride);
+    }
+
+    /// The index of the ride on `trip` through `stop_id`, if it was ridden that far.
+    pub fn ride_index(&self, trip: TripKey, stop_id: StopId) -> Option<usize> {
+        self.rides_through.get(&(trip, stop_id)).copied()
+    }
+
+    pub fn ride(&self, idx: usize) -> &Ride {
+        &self.rides[idx]
+    }
+
+    /// The earliest arrival at `stop_id`, using as few trips as possible.
+    pub fn earliest_arrival(&self, stop_id: StopId) -> Option<&Label> {
+        self.arrivals
+            .get(&stop_id)?
+            .iter()
+            .map(|&i| &self.labels[i])
+            .min_by_key(|l| (l.time, l.trips))
+    }
+
+    /// The earliest arrival at every reached stop.
+    pub fn earliest_arrivals(&self) -> impl Iterator<Item = &Label> {
+        self.arrivals
+            .keys()
+            .filter_map(|&stop_id| self.earliest_arrival(stop_id))
+    }
+
+    fn insert(&mut self, bag: &Bag, label: Label) -> Option<usize> {
+        if self.is_dominated(bag, &label) {
+            return None;
+        }
+
+        let idx = self.labels.len();
+        self.labels.push(label);
+        self.insert_existing(bag, idx);
+        Some(idx)
+    }
+
+    fn insert_existing(&mut self, bag: &Bag, idx: usize) {
+        if self.is_dominated(bag, &self.labels[idx]) {
+            return;
+        }
+
+        let Label {
+            stop_id,
+            time,
+            trips,
+            ..
+        } = self.labels[idx];
+        let labels = &self.labels;
+        let keep = |&i: &usize| labels[i].time < time || labels[i].trips < trips;
+        match bag {
+            Bag::Arrivals => {
+                let bag = self.arrivals.entry(stop_id).or_default();
+                bag.retain(keep);
+                bag.push(idx);
+            }
+            Bag::Changes(group) => {
+                let groups = self.changes.entry(stop_id).or_default();
+                // Being ready on foot beats being ready at the same time off any train
+                if *group == ChangeGroup::OnFoot {
+                    groups.values_mut().for_each(|bag| bag.retain(keep));
+                } else if let Some(bag) = groups.get_mut(group) {
+                    bag.retain(keep);
+                }
+                groups.entry(group.clone()).or_default().push(idx);
+            }
+        }
+    }
+
+    fn is_dominated(&self, bag: &Bag, label: &Label) -> bool {
+        let dominates = |&i: &usize| {
+            let other = &self.labels[i];
+            other.time <= label.time && other.trips <= label.trips
+        };
+
+        match bag {
+            Bag::Arrivals => self
+                .arrivals
+                .get(&label.stop_id)
+                .is_some_and(|bag| bag.iter().any(dominates)),
+            Bag::Changes(group) => self.changes.get(&label.stop_id).is_some_and(|groups| {
+                [group, &ChangeGroup::OnFoot]
+                    .into_iter()
+                    .filter_map(|g| groups.get(g))
+                    .flatten()
+                    .any(dominates)
+            }),
+        }
+    }
+}
+
+enum Bag {
+    Arrivals,
+    Changes(ChangeGroup),
+}
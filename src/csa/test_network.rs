@@ -0,0 +1,146 @@
+//! Small networks built in memory for testing the scans.
+
+use std::{collections::HashMap, convert::Infallible};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+
+use super::{
+    Calendar, Connection, CsaAdapter, Service, ServiceInfo, ServiceKind, StaySeated, Stop, StopId,
+    Transfer, TransportNetwork, TripId,
+};
+
+/// The day every trip runs on.
+pub(super) fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 3, 3).unwrap()
+}
+
+pub(super) fn time(hh_mm: &str) -> NaiveTime {
+    NaiveTime::parse_from_str(hh_mm, "%H:%M").unwrap()
+}
+
+pub(super) fn date_time(hh_mm: &str) -> NaiveDateTime {
+    NaiveDateTime::new(date(), time(hh_mm))
+}
+
+/// A network of stops named S0, S1 and so on, too far apart to walk between, and
+/// trips that only run on `date()`.
+#[derive(Default)]
+pub(super) struct TestNetwork {
+    stops: HashMap<StopId, Stop>,
+    connections: Vec<Connection>,
+    transfers: HashMap<StopId, Vec<Transfer>>,
+    stay_seated: Vec<StaySeated>,
+    services: HashMap<TripId, Vec<Service>>,
+}
+
+impl TestNetwork {
+    pub fn new(stops: u64) -> Self {
+        let stops = (0..stops)
+            .map(|i| {
+                let lat = 51.0 + i as f64 / 10.0;
+                let stop = Stop::new(format!("S{i}"), lat, -1.0, TimeDelta::zero());
+                (StopId(i), stop)
+            })
+            .collect();
+
+        Self {
+            stops,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the minimum change time at `stop`.
+    pub fn change_time(mut self, stop: u64, minutes: i64) -> Self {
+        self.stops.get_mut(&StopId(stop)).unwrap().min_change_time = TimeDelta::minutes(minutes);
+        self
+    }
+
+    /// Adds a trip calling at each stop at the time given, leaving as soon as it
+    /// arrives.
+    pub fn trip(mut self, trip: u64, calls: &[(u64, &str)]) -> Self {
+        let seconds = |hh_mm| {
+            time(hh_mm)
+                .signed_duration_since(NaiveTime::MIN)
+                .num_seconds() as u32
+        };
+
+        for pair in calls.windows(2) {
+            let [(from, departure), (to, arrival)] = pair else {
+                unreachable!()
+            };
+            self.connections.push(Connection {
+                trip_id: TripId(trip),
+                from_stop_id: StopId(*from),
+                to_stop_id: StopId(*to),
+                departure_time: seconds(departure),
+                arrival_time: seconds(arrival),
+                can_board: true,
+                can_alight: true,
+                service: ServiceInfo {
+                    operator: None,
+                    category: None,
+                    service_code: None,
+                    kind: ServiceKind::Train,
+                },
+                departure_platform: None,
+                arrival_platform: None,
+            });
+        }
+        self.services.insert(TripId(trip), vec![every_day()]);
+        self
+    }
+
+    pub fn transfer(mut self, transfer: Transfer) -> Self {
+        self.transfers
+            .entry(transfer.from_stop_id)
+            .or_default()
+            .push(transfer);
+        self
+    }
+
+    /// Lets passengers on `from_trip` stay on board for `to_trip` at `stop`.
+    pub fn stay_seated(mut self, from_trip: u64, to_trip: u64, stop: u64) -> Self {
+        self.stay_seated.push(StaySeated {
+            from_trip_id: TripId(from_trip),
+            to_trip_id: TripId(to_trip),
+            stop_id: StopId(stop),
+            day_offset: 0,
+            onward_dates: false,
+            services: vec![every_day()],
+            cancellations: vec![],
+        });
+        self
+    }
+
+    pub fn build(self) -> TransportNetwork {
+        TransportNetwork::from_adapter(&self).unwrap()
+    }
+}
+
+fn every_day() -> Service {
+    Service::new(date(), date(), [true; 7])
+}
+
+impl CsaAdapter for TestNetwork {
+    type Error = Infallible;
+
+    fn stops(&self) -> Result<HashMap<StopId, Stop>, Infallible> {
+        Ok(self.stops.clone())
+    }
+
+    fn connections(&self) -> Result<Vec<Connection>, Infallible> {
+        Ok(self.connections.clone())
+    }
+
+    fn transfers(&self) -> Result<HashMap<StopId, Vec<Transfer>>, Infallible> {
+        Ok(self.transfers.clone())
+    }
+
+    fn calendar(&self) -> Result<Calendar, Infallible> {
+        Ok(Calendar::new(self.services.clone(), HashMap::new()))
+    }
+
+    fn stay_seated(&self) -> Result<Vec<StaySeated>, Infallible> {
+        Ok(self.stay_seated.clone())
+    }
+}
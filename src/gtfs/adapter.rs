@@ -0,0 +1,296 @@
+use anyhow::Result;
+use chrono::TimeDelta;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+use crate::{
+    adapters::CsaAdapter,
+    csa::{
+        Calendar, Connection, Service, ServiceInfo, ServiceKind, Stop, StopId, Transfer,
+        TransferMode, TripId,
+    },
+    gtfs::{GtfsFeed, records::TransferRecord},
+};
+
+pub struct GtfsAdapter<'a> {
+    feed: &'a GtfsFeed,
+    gtfs_trip_to_trip_id: HashMap<String, TripId>,
+    trip_kinds: HashMap<TripId, ServiceKind>,
+    gtfs_stop_to_stop_id: HashMap<String, StopId>,
+    platforms: HashMap<StopId, String>,
+    /// Platforms grouped by the station they belong to, keyed by the station's GTFS
+    /// stop ID.
+    station_platforms: HashMap<&'a str, Vec<StopId>>,
+    stops: HashMap<StopId, Stop>,
+}
+
+impl<'a> GtfsAdapter<'a> {
+    pub fn new(feed: &'a GtfsFeed) -> Result<Self> {
+        // A transfer from a stop to itself gives the time needed to change there
+        let min_change_times: HashMap<&str, TimeDelta> = feed
+            .transfers
+            .iter()
+            .filter(|t| t.from_stop_id == t.to_stop_id)
+            .filter_map(|t| Some((t.from_stop_id.as_str(), transfer_time(t)?)))
+            .collect();
+
+        let mut gtfs_stop_to_stop_id = HashMap::new();
+        let mut platforms = HashMap::new();
+        let mut station_platforms: HashMap<&str, Vec<StopId>> = HashMap::new();
+        let mut stops = HashMap::new();
+
+        // Stations, entrances and the like have no stop times, so only stops and
+        // platforms are kept
+        let boardable = feed
+            .stops
+            .iter()
+            .filter(|s| s.location_type.is_none_or(|t| t == 0));
+
+        for (i, s) in boardable.enumerate() {
+            let (Some(lat), Some(lon)) = (s.stop_lat, s.stop_lon) else {
+                continue;
+            };
+
+            let id = StopId::new(i as u64);
+            let name = s.stop_name.clone().unwrap_or_else(|| s.stop_id.clone());
+            // Platforms without a change time of their own use their station's
+            let parent = s.parent_station.as_deref().filter(|p| !p.is_empty());
+            let min_change_time = min_change_times
+                .get(s.stop_id.as_str())
+                .or_else(|| min_change_times.get(parent?))
+                .copied()
+                .unwrap_or_default();
+
+            let mut stop = Stop::new(name, lat, lon, min_change_time);
+            stop.codes = vec![s.stop_id.clone()];
+            stops.insert(id, stop);
+            gtfs_stop_to_stop_id.insert(s.stop_id.clone(), id);
+            if let Some(platform) = s.platform_code.clone().filter(|p| !p.is_empty()) {
+                platforms.insert(id, platform);
+            }
+            if let Some(parent) = parent {
+                station_platforms.entry(parent).or_default().push(id);
+            }
+        }
+
+        let gtfs_trip_to_trip_id: HashMap<String, TripId> = feed
+            .trips
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.trip_id.clone(), TripId::new(i as u64)))
+            .collect();
+
+        // Feeds without routes.txt are assumed to be all rail
+        let route_kinds: HashMap<&str, ServiceKind> = feed
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r.service_kind()))
+            .collect();
+        let trip_kinds = feed
+            .trips
+            .iter()
+            .filter_map(|t| {
+                let kind = *route_kinds.get(t.route_id.as_str())?;
+                Some((gtfs_trip_to_trip_id[&t.trip_id], kind))
+            })
+            .collect();
+
+        Ok(Self {
+            feed,
+            gtfs_trip_to_trip_id,
+            trip_kinds,
+            gtfs_stop_to_stop_id,
+            platforms,
+            station_platforms,
+            stops,
+        })
+    }
+
+    /// Stops that a GTFS stop ID refers to: the stop itself, or every platform of a
+    /// station.
+    fn resolve_stop(&self, gtfs_stop_id: &str) -> &[StopId] {
+        match self.gtfs_stop_to_stop_id.get(gtfs_stop_id) {
+            Some(id) => std::slice::from_ref(id),
+            None => self
+                .station_platforms
+                .get(gtfs_stop_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Time taken by a transfer, or `None` if it isn't possible.
+fn transfer_time(transfer: &TransferRecord) -> Option<TimeDelta> {
+    match transfer.transfer_type.unwrap_or(0) {
+        0..=2 => Some(TimeDelta::seconds(
+            transfer.min_transfer_time.unwrap_or(0).into(),
+        )),
+        // Transfers that aren't possible, and in-seat transfers which are handled
+        // by staying on the vehicle
+        _ => None,
+    }
+}
+
+impl<'a> CsaAdapter for GtfsAdapter<'a> {
+    type Error = anyhow::Error;
+
+    fn stops(&self) -> Result<HashMap<StopId, Stop>> {
+        Ok(self.stops.clone())
+    }
+
+    fn calendar(&self) -> Result<Calendar> {
+        let regular = self
+            .feed
+            .calendar
+            .iter()
+            .into_group_map_by(|c| c.service_id.as_str());
+        let (added, removed): (Vec<_>, Vec<_>) = self
+            .feed
+            .calendar_dates
+            .iter()
+            .partition(|d| d.exception_type == 1);
+        let added = added
+            .into_iter()
+            .into_group_map_by(|d| d.service_id.as_str());
+        let removed = removed
+            .into_iter()
+            .into_group_map_by(|d| d.service_id.as_str());
+
+        let mut services: HashMap<TripId, Vec<Service>> = HashMap::new();
+        let mut cancellations: HashMap<TripId, Vec<Service>> = HashMap::new();
+
+        for trip in &self.feed.trips {
+            let trip_id = self.gtfs_trip_to_trip_id[&trip.trip_id];
+            let service_id = trip.service_id.as_str();
+
+            let trip_services = services.entry(trip_id).or_default();
+            for c in regular.get(service_id).into_iter().flatten() {
+                trip_services.push(Service::new(c.start_date, c.end_date, c.days_run()));
+            }
+            for d in added.get(service_id).into_iter().flatten() {
+                trip_services.push(Service::new(d.date, d.date, [true; 7]));
+            }
+
+            for d in removed.get(service_id).into_iter().flatten() {
+                cancellations
+                    .entry(trip_id)
+                    .or_default()
+                    .push(Service::new(d.date, d.date, [true; 7]));
+            }
+        }
+
+        Ok(Calendar::new(services, cancellations))
+    }
+
+    fn connections(&self) -> Result<Vec<Connection>> {
+        let stop_times = self
+            .feed
+            .stop_times
+            .iter()
+            .into_group_map_by(|st| st.trip_id.as_str());
+
+        let mut connections = vec![];
+
+        for (gtfs_trip_id, mut stop_times) in stop_times {
+            let Some(&trip_id) = self.gtfs_trip_to_trip_id.get(gtfs_trip_id) else {
+                continue;
+            };
+            stop_times.sort_unstable_by_key(|st| st.stop_sequence);
+
+            // Stops without times are skipped rather than interpolated
+            let timed: Vec<_> = stop_times
+                .into_iter()
+                .filter(|st| st.arrival_time.is_some() || st.departure_time.is_some())
+                .filter_map(|st| Some((self.gtfs_stop_to_stop_id.get(&st.stop_id)?, st)))
+                .collect();
+
+            let service = ServiceInfo {
+                operator: None,
+                category: None,
+                service_code: None,
+                kind: self
+                    .trip_kinds
+                    .get(&trip_id)
+                    .copied()
+                    .unwrap_or(ServiceKind::Train),
+            };
+
+            for pair in timed.windows(2) {
+                let (&from_stop_id, from) = pair[0];
+                let (&to_stop_id, to) = pair[1];
+
+                let departure_time = from.departure_time.or(from.arrival_time);
+                let arrival_time = to.arrival_time.or(to.departure_time);
+                let (Some(departure_time), Some(arrival_time)) = (departure_time, arrival_time)
+                else {
+                    continue;
+                };
+
+                connections.push(Connection {
+                    trip_id,
+                    from_stop_id,
+                    to_stop_id,
+                    departure_time,
+                    arrival_time,
+                    can_board: from.pickup_type != Some(1),
+                    can_alight: to.drop_off_type != Some(1),
+                    service: service.clone(),
+                    departure_platform: self.platforms.get(&from_stop_id).cloned(),
+                    arrival_platform: self.platforms.get(&to_stop_id).cloned(),
+                });
+            }
+        }
+
+        Ok(connections)
+    }
+
+    fn transfers(&self) -> Result<HashMap<StopId, Vec<Transfer>>, Self::Error> {
+        let mut transfer_times: HashMap<(StopId, StopId), TimeDelta> = HashMap::new();
+
+        // Transfers between stations apply to every pair of their platforms
+        for t in self
+            .feed
+            .transfers
+            .iter()
+            .filter(|t| t.from_stop_id != t.to_stop_id)
+        {
+            let Some(time) = transfer_time(t) else {
+                continue;
+            };
+            let from = self.resolve_stop(&t.from_stop_id);
+            let to = self.resolve_stop(&t.to_stop_id);
+            for (&from, &to) in from.iter().cartesian_product(to) {
+                if from != to {
+                    transfer_times.insert((from, to), time);
+                }
+            }
+        }
+
+        // Platforms of the same station are linked even when the feed doesn't say so,
+        // taking the station's minimum change time
+        for platforms in self.station_platforms.values() {
+            for (&from, &to) in platforms.iter().tuple_combinations() {
+                let time = self.stops[&from]
+                    .min_change_time
+                    .max(self.stops[&to].min_change_time);
+                transfer_times.entry((from, to)).or_insert(time);
+                transfer_times.entry((to, from)).or_insert(time);
+            }
+        }
+
+        let transfers = transfer_times
+            .into_iter()
+            .map(|((from_stop_id, to_stop_id), transfer_time)| Transfer {
+                from_stop_id,
+                to_stop_id,
+                transfer_time,
+                validity: None,
+                mode: TransferMode::Walk,
+                priority: None,
+            })
+            .into_group_map_by(|t| t.from_stop_id);
+
+        Ok(transfers)
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result, ensure};
+use serde::de::DeserializeOwned;
+use std::{fs::File, io::Read, path::Path};
+use zip::ZipArchive;
+
+mod adapter;
+mod records;
+
+use records::{
+    CalendarDateRecord, CalendarRecord, RouteRecord, StopRecord, StopTimeRecord, TransferRecord,
+    TripRecord,
+};
+
+pub use adapter::GtfsAdapter;
+
+use crate::csa::TransportNetwork;
+
+pub struct GtfsFeed {
+    pub stops: Vec<StopRecord>,
+    pub routes: Vec<RouteRecord>,
+    pub trips: Vec<TripRecord>,
+    pub stop_times: Vec<StopTimeRecord>,
+    pub calendar: Vec<CalendarRecord>,
+    pub calendar_dates: Vec<CalendarDateRecord>,
+    pub transfers: Vec<TransferRecord>,
+}
+
+impl GtfsFeed {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let mut archive = ZipArchive::new(file)?;
+
+        let mut stops = None;
+        let mut routes = None;
+        let mut trips = None;
+        let mut stop_times = None;
+        let mut calendar = None;
+        let mut calendar_dates = None;
+        let mut transfers = None;
+
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+            // Some feeds are zipped with their files inside a folder
+            let name = file.name().rsplit('/').next().unwrap_or_default();
+
+            match name {
+                "stops.txt" => stops = Some(parse_csv(file, "stops.txt")?),
+                "routes.txt" => routes = Some(parse_csv(file, "routes.txt")?),
+                "trips.txt" => trips = Some(parse_csv(file, "trips.txt")?),
+                "stop_times.txt" => stop_times = Some(parse_csv(file, "stop_times.txt")?),
+                "calendar.txt" => calendar = Some(parse_csv(file, "calendar.txt")?),
+                "calendar_dates.txt" => {
+                    calendar_dates = Some(parse_csv(file, "calendar_dates.txt")?)
+                }
+                "transfers.txt" => transfers = Some(parse_csv(file, "transfers.txt")?),
+                _ => continue,
+            }
+        }
+
+        ensure!(
+            calendar.is_some() || calendar_dates.is_some(),
+            "Missing calendar.txt and calendar_dates.txt"
+        );
+
+        Ok(Self {
+            stops: stops.context("Missing stops.txt")?,
+            routes: routes.unwrap_or_default(),
+            trips: trips.context("Missing trips.txt")?,
+            stop_times: stop_times.context("Missing stop_times.txt")?,
+            calendar: calendar.unwrap_or_default(),
+            calendar_dates: calendar_dates.unwrap_or_default(),
+            transfers: transfers.unwrap_or_default(),
+        })
+    }
+}
+
+fn parse_csv<T: DeserializeOwned, R: Read>(reader: R, name: &str) -> Result<Vec<T>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("parsing {name}"))
+}
+
+impl TransportNetwork {
+    pub fn from_gtfs(feed: &GtfsFeed) -> Result<Self> {
+        let adapter = GtfsAdapter::new(feed)?;
+        TransportNetwork::from_adapter(&adapter)
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::csa::ServiceKind;
+
+#[derive(Debug, Deserialize)]
+pub struct StopRecord {
+    pub stop_id: String,
+    pub stop_name: Option<String>,
+    pub stop_lat: Option<f64>,
+    pub stop_lon: Option<f64>,
+    /// 0 or empty for a stop or platform, 1 for a station, and higher values for
+    /// entrances and other parts of a station.
+    pub location_type: Option<u8>,
+    pub parent_station: Option<String>,
+    /// Platform identifier for a stop that is a platform, such as "4".
+    pub platform_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouteRecord {
+    pub route_id: String,
+    /// 0 for tram, 1 for metro, 2 for rail, 3 for bus, 4 for ferry and so on, or an
+    /// extended type such as 700 for bus services or 1100 for air services.
+    pub route_type: u16,
+}
+
+impl RouteRecord {
+    pub fn service_kind(&self) -> ServiceKind {
+        match self.route_type {
+            714 => ServiceKind::ReplacementBus,
+            3 | 11 | 200..=299 | 700..=799 | 800 => ServiceKind::Bus,
+            4 | 1000..=1099 | 1200..=1299 => ServiceKind::Ship,
+            // Trams, metros, monorails, funiculars and cable trams run on rails too
+            0..=2 | 5 | 7 | 12 | 100..=199 | 400..=599 | 900..=999 | 1400..=1499 => {
+                ServiceKind::Train
+            }
+            _ => ServiceKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TripRecord {
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StopTimeRecord {
+    pub trip_id: String,
+    #[serde(deserialize_with = "gtfs_time")]
+    pub arrival_time: Option<u32>,
+    #[serde(deserialize_with = "gtfs_time")]
+    pub departure_time: Option<u32>,
+    pub stop_id: String,
+    pub stop_sequence: u32,
+    /// 0 or empty for regular pick-up, 1 where passengers can't board, and 2 or 3
+    /// where pick-up must be arranged.
+    pub pickup_type: Option<u8>,
+    /// As for `pickup_type`, for passengers alighting.
+    pub drop_off_type: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarRecord {
+    pub service_id: String,
+    pub monday: u8,
+    pub tuesday: u8,
+    pub wednesday: u8,
+    pub thursday: u8,
+    pub friday: u8,
+    pub saturday: u8,
+    pub sunday: u8,
+    #[serde(deserialize_with = "gtfs_date")]
+    pub start_date: NaiveDate,
+    #[serde(deserialize_with = "gtfs_date")]
+    pub end_date: NaiveDate,
+}
+
+impl CalendarRecord {
+    pub fn days_run(&self) -> [bool; 7] {
+        [
+            self.monday,
+            self.tuesday,
+            self.wednesday,
+            self.thursday,
+            self.friday,
+            self.saturday,
+            self.sunday,
+        ]
+        .map(|d| d == 1)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarDateRecord {
+    pub service_id: String,
+    #[serde(deserialize_with = "gtfs_date")]
+    pub date: NaiveDate,
+    /// 1 if service was added on this date, 2 if it was removed.
+    pub exception_type: u8,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRecord {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    /// 0 or empty for a recommended transfer, 1 for a timed transfer, 2 for one that
+    /// needs `min_transfer_time`, 3 where transfers are not possible.
+    pub transfer_type: Option<u8>,
+    pub min_transfer_time: Option<u32>,
+}
+
+/// Parses `HH:MM:SS` as seconds since the start of the service day. Hours can go past
+/// 24 for trips that run through midnight.
+fn gtfs_time<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    let Some(s) = Option::<String>::deserialize(d)? else {
+        return Ok(None);
+    };
+
+    let parts: Vec<u32> = s
+        .trim()
+        .split(':')
+        .map(|p| p.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| D::Error::custom(format!("bad time (HH:MM:SS): {s}")))?;
+
+    match parts[..] {
+        [h, m, s] => Ok(Some(h * 3600 + m * 60 + s)),
+        _ => Err(D::Error::custom(format!("bad time (HH:MM:SS): {s}"))),
+    }
+}
+
+fn gtfs_date<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveDate, D::Error> {
+    let s = String::deserialize(d)?;
+    NaiveDate::parse_from_str(s.trim(), "%Y%m%d")
+        .map_err(|_| D::Error::custom(format!("bad date (YYYYMMDD): {s}")))
+}
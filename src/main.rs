@@ -1,15 +1,19 @@
+use anyhow::{Context, bail, ensure};
 use axum::{
     Json, Router,
     extract::{Query, State},
     http::{Method, StatusCode},
     routing::get,
 };
-use chrono::{NaiveDate, NaiveTime};
-use clap::{Parser, Subcommand};
+use chrono::{NaiveDate, NaiveTime, TimeDelta};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use geojson::FeatureCollection;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::{
+    collections::{HashMap, HashSet},
+    fmt,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 use tower_http::cors::CorsLayer;
@@ -18,9 +22,17 @@ use tracing::info;
 mod adapters;
 mod cif;
 mod csa;
+mod gtfs;
+mod polygons;
 use crate::{
-    cif::CifTimetable,
-    csa::{TransportNetwork, to_feature_collection},
+    adapters::MergedAdapter,
+    cif::{CifAdapter, CifTimetable, read_station_overrides, read_tiploc_mappings},
+    csa::{
+        DepartureBoard, Journey, OperatorFilter, Origin, QueryOptions, ServiceKind, StopId,
+        StopMatch, TransferMode, TransportNetwork, to_feature_collection,
+    },
+    gtfs::{GtfsAdapter, GtfsFeed},
+    polygons::{PolygonOptions, isochrone_polygons},
 };
 
 #[derive(Parser)]
@@ -31,25 +43,326 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    Import {
-        timetable_path: PathBuf,
-        #[arg(default_value = "./network.pc")]
+    Import(ImportArgs),
+    Query {
         network_path: PathBuf,
+        /// Latitude and longitude of the origin, unless it is given by `--from` or
+        /// `--from-stop`, then the date and time.
+        #[arg(
+            value_name = "[LAT LON] DATE TIME",
+            required = true,
+            allow_negative_numbers = true
+        )]
+        positionals: Vec<String>,
+        /// End of the departure window; returns a profile of journeys leaving between
+        /// `time` and `until` instead of a single snapshot.
+        #[arg(long)]
+        until: Option<NaiveTime>,
+        /// Treat `date` and `time` as a deadline and find the latest departure from each
+        /// station that still reaches the given location in time.
+        #[arg(long, conflicts_with = "until")]
+        arrive_by: bool,
+        /// Return one polygon per time band instead of a point per station.
+        #[arg(long, conflicts_with_all = ["until", "arrive_by"])]
+        polygons: bool,
+        #[command(flatten)]
+        origin: OriginArgs,
+        #[command(flatten)]
+        options: QueryArgs,
+        #[command(flatten)]
+        polygon_options: PolygonArgs,
     },
-    Query {
+    Journey {
+        network_path: PathBuf,
+        /// Latitude and longitude of the origin, unless it is given by `--from` or
+        /// `--from-stop`, then the date, the time and the stop ID of the destination, as
+        /// reported by `query`.
+        #[arg(
+            value_name = "[LAT LON] DATE TIME TO",
+            required = true,
+            allow_negative_numbers = true
+        )]
+        positionals: Vec<String>,
+        #[command(flatten)]
+        origin: OriginArgs,
+        #[command(flatten)]
+        options: QueryArgs,
+    },
+    /// List the trips that can be boarded at a stop.
+    Departures {
         network_path: PathBuf,
-        #[arg(allow_hyphen_values = true)]
-        lat: f64,
-        #[arg(allow_hyphen_values = true)]
-        lon: f64,
+        /// Stop ID, as reported by `query`.
+        stop: u64,
         date: NaiveDate,
         time: NaiveTime,
+        /// How far ahead of `time` to list departures, in minutes, up to a week.
+        #[arg(long, default_value_t = 60)]
+        window: u32,
+        #[command(flatten)]
+        options: QueryArgs,
+    },
+    /// Search for stops by name, alias, CRS code or TIPLOC.
+    Stops {
+        network_path: PathBuf,
+        query: String,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
     },
     Serve {
         network_path: PathBuf,
     },
 }
 
+#[derive(Args)]
+struct ImportArgs {
+    /// Timetables to combine into one network, followed by where to save the network
+    /// unless `--output` is given. A lone timetable is saved to `./network.pc`.
+    #[arg(required = true, value_name = "PATHS")]
+    paths: Vec<PathBuf>,
+    /// Where to save the network, so that every path before it is a timetable.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// JSON file of station locations to use instead of the grid references in the
+    /// timetable, such as `stations.json` from `uk-railway-stations`.
+    #[arg(long)]
+    stations: Option<PathBuf>,
+    /// JSON object mapping TIPLOCs missing from the MSN to the CRS codes of their
+    /// stations, such as `{"CLPHMJW": "CLJ"}`.
+    #[arg(long)]
+    tiploc_mappings: Option<PathBuf>,
+    /// Format of each timetable, separated by commas. A single format applies to
+    /// every timetable.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "cif")]
+    format: Vec<TimetableFormat>,
+    /// CIF update extracts to apply, in order, on top of the CIF timetable.
+    #[arg(long)]
+    update: Vec<PathBuf>,
+    /// Distance in metres within which stops from different timetables are linked
+    /// by walking transfers.
+    #[arg(long, default_value_t = 250.0)]
+    link_radius: f64,
+    /// Kinds of service to leave out of the network, separated by commas: `train`,
+    /// `replacement-bus`, `bus`, `ship` or `other`.
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<ServiceKind>,
+}
+
+impl ImportArgs {
+    /// Splits the paths into the timetables and where to save the network.
+    fn timetables_and_output(&self) -> anyhow::Result<(&[PathBuf], PathBuf)> {
+        if let Some(output) = &self.output {
+            return Ok((&self.paths, output.clone()));
+        }
+
+        match self.paths.split_last() {
+            Some((output, timetables)) if !timetables.is_empty() => {
+                // Guards against a forgotten network path overwriting a timetable
+                ensure!(
+                    output
+                        .extension()
+                        .is_none_or(|e| !e.eq_ignore_ascii_case("zip")),
+                    "{output:?} looks like a timetable, so give the network path with --output"
+                );
+                Ok((timetables, output.clone()))
+            }
+            _ => Ok((&self.paths, PathBuf::from("./network.pc"))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TimetableFormat {
+    /// A zipped CIF timetable from the Rail Delivery Group.
+    Cif,
+    /// A zipped GTFS feed.
+    Gtfs,
+}
+
+/// Where a query starts: either a point, from which the stops within walking distance
+/// are reached on foot, or a stop itself. On the command line, the point is given by
+/// the first positional arguments.
+#[derive(Args, Deserialize)]
+struct OriginArgs {
+    #[arg(skip)]
+    lat: Option<f64>,
+    #[arg(skip)]
+    lon: Option<f64>,
+    /// Start at the station with this CRS code or TIPLOC.
+    #[arg(long, conflicts_with = "from_stop")]
+    from: Option<String>,
+    /// Start at the stop with this ID, as reported by `query`.
+    #[arg(long)]
+    from_stop: Option<u64>,
+}
+
+impl OriginArgs {
+    /// Takes the latitude and longitude from the front of a command's positional
+    /// arguments, unless the origin is given by `--from` or `--from-stop`, and returns
+    /// the `N` arguments after them.
+    fn take_point<const N: usize>(
+        &mut self,
+        positionals: Vec<String>,
+    ) -> anyhow::Result<[String; N]> {
+        let at_stop = self.from.is_some() || self.from_stop.is_some();
+        let rest = match positionals.len() {
+            len if len == N + 2 && !at_stop => {
+                self.lat = Some(positionals[0].parse().context("invalid latitude")?);
+                self.lon = Some(positionals[1].parse().context("invalid longitude")?);
+                positionals[2..].to_vec()
+            }
+            len if len == N && at_stop => positionals,
+            _ if at_stop => bail!("a latitude and longitude can't be given as well as a stop"),
+            _ => bail!("expected a latitude and longitude, or --from or --from-stop"),
+        };
+
+        Ok(rest.try_into().expect("checked the number of arguments"))
+    }
+
+    fn resolve(&self, network: &TransportNetwork) -> anyhow::Result<Origin> {
+        match (self.lat, self.lon, &self.from, self.from_stop) {
+            (Some(lat), Some(lon), None, None) => Ok(Origin::Point { lat, lon }),
+            (None, None, Some(code), None) => network
+                .stop_by_code(code)
+                .map(Origin::Stop)
+                .with_context(|| format!("no stop with code {code}")),
+            (None, None, None, Some(id)) => {
+                let stop_id = StopId::new(id);
+                ensure!(network.has_stop(stop_id), "no stop with ID {id}");
+                Ok(Origin::Stop(stop_id))
+            }
+            _ => bail!("expected either lat and lon, from or from_stop"),
+        }
+    }
+}
+
+/// Query settings shared by the CLI commands and the HTTP endpoints.
+#[derive(Args, Deserialize)]
+struct QueryArgs {
+    /// Maximum number of changes between trips.
+    #[arg(long)]
+    max_transfers: Option<u32>,
+    /// How long after departure (or before the deadline) to look for journeys, in minutes.
+    /// A day by default, and at most a week.
+    #[arg(long)]
+    horizon: Option<u32>,
+    /// Only use trips run by these operators, given as ATOC codes separated by commas.
+    #[arg(long, value_delimiter = ',', conflicts_with = "exclude_operators")]
+    #[serde(default, deserialize_with = "comma_separated")]
+    operators: Option<Vec<String>>,
+    /// Don't use trips run by these operators, given as ATOC codes separated by commas.
+    #[arg(long, value_delimiter = ',', conflicts_with = "operators")]
+    #[serde(default, deserialize_with = "comma_separated")]
+    exclude_operators: Option<Vec<String>>,
+    /// Kinds of service not to use, separated by commas: `train`, `replacement-bus`,
+    /// `bus`, `ship` or `other`. Excluding all but `train` gives a trains-only
+    /// isochrone.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "comma_separated")]
+    exclude: Option<Vec<ServiceKind>>,
+    /// Ways of making transfers to allow, separated by commas: `walk`, `bus`, `tube`,
+    /// `ferry`, `metro`, `tram`, `taxi` or `transfer`. All are allowed by default.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "comma_separated")]
+    modes: Option<Vec<TransferMode>>,
+    /// Ways of making transfers not to allow, such as `taxi`.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "comma_separated")]
+    exclude_modes: Option<Vec<TransferMode>>,
+    /// How far from the origin to look for stops, in metres as the crow flies.
+    /// 1000 by default.
+    #[arg(long)]
+    access_radius: Option<f64>,
+    /// Speed of getting to stops near the origin in metres per second: 1.4 by default
+    /// for walking, or around 4 for cycling.
+    #[arg(long)]
+    access_speed: Option<f64>,
+    /// How much longer routes to stops near the origin are than straight lines, at
+    /// least 1 and 1 by default.
+    #[arg(long)]
+    detour_factor: Option<f64>,
+    /// Minutes added to reach the first stop, such as for parking a bike.
+    #[arg(long)]
+    access_penalty: Option<u32>,
+}
+
+impl TryFrom<QueryArgs> for QueryOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(args: QueryArgs) -> anyhow::Result<Self> {
+        let defaults = QueryOptions::default();
+        let operators = match (args.operators, args.exclude_operators) {
+            (Some(_), Some(_)) => bail!("give either operators or exclude_operators, not both"),
+            (Some(only), None) => OperatorFilter::Only(only.into_iter().collect()),
+            (None, Some(except)) => OperatorFilter::Except(except.into_iter().collect()),
+            (None, None) => defaults.operators,
+        };
+
+        let excluded_modes: HashSet<TransferMode> =
+            args.exclude_modes.into_iter().flatten().collect();
+        let transfer_modes = args
+            .modes
+            .map_or(defaults.transfer_modes, |m| m.into_iter().collect())
+            .into_iter()
+            .filter(|m| !excluded_modes.contains(m))
+            .collect();
+
+        Ok(QueryOptions {
+            max_transfers: args.max_transfers,
+            horizon: args
+                .horizon
+                .map_or(defaults.horizon, |m| TimeDelta::minutes(m.into())),
+            operators,
+            excluded_kinds: args.exclude.into_iter().flatten().collect(),
+            transfer_modes,
+            access_radius: args.access_radius.unwrap_or(defaults.access_radius),
+            access_speed: args.access_speed.unwrap_or(defaults.access_speed),
+            detour_factor: args.detour_factor.unwrap_or(defaults.detour_factor),
+            access_penalty: args
+                .access_penalty
+                .map_or(defaults.access_penalty, |m| TimeDelta::minutes(m.into())),
+        })
+    }
+}
+
+/// Settings for turning arrival times into isochrone polygons.
+#[derive(Args, Deserialize)]
+struct PolygonArgs {
+    /// Upper edges of the time bands in minutes, up to a day, separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "comma_separated")]
+    bands: Option<Vec<u32>>,
+    /// Walking speed from stations in metres per second, at most 10.
+    #[arg(long)]
+    walking_speed: Option<f64>,
+}
+
+impl From<PolygonArgs> for PolygonOptions {
+    fn from(args: PolygonArgs) -> Self {
+        let defaults = PolygonOptions::default();
+        PolygonOptions {
+            bands: args.bands.unwrap_or(defaults.bands),
+            walking_speed: args.walking_speed.unwrap_or(defaults.walking_speed),
+        }
+    }
+}
+
+/// Query strings can't repeat keys into a `Vec`, so lists are given as `30,60,90`.
+fn comma_separated<'de, D, T>(d: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Some(s) = Option::<String>::deserialize(d)? else {
+        return Ok(None);
+    };
+
+    s.split(',')
+        .map(|b| b.trim().parse().map_err(serde::de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -59,23 +372,91 @@ async fn main() {
     let args = Cli::parse();
 
     match args.command {
-        Commands::Import {
-            timetable_path,
+        Commands::Import(args) => {
+            import_timetables(args).expect("Unable to import timetable");
+        }
+        Commands::Query {
             network_path,
+            positionals,
+            until,
+            arrive_by,
+            polygons,
+            mut origin,
+            options,
+            polygon_options,
         } => {
-            import_timetable(timetable_path, network_path).expect("Unable to import CIF timetable");
+            let [date, time] = origin.take_point(positionals).expect("Invalid arguments");
+            let date: NaiveDate = date.parse().expect("Invalid date");
+            let time: NaiveTime = time.parse().expect("Invalid time");
+            let network = TransportNetwork::load(network_path).expect("Failed to load network");
+            let origin = origin.resolve(&network).expect("Invalid origin");
+            let options = QueryOptions::try_from(options).expect("Invalid query options");
+            let params = IsochroneParams {
+                date,
+                time,
+                until,
+                arrive_by,
+            };
+            let geojson = if polygons {
+                run_polygon_query(&network, &origin, params, &options, &polygon_options.into())
+            } else {
+                run_query(&network, &origin, params, &options)
+            }
+            .expect("Failed to execute query");
+            println!("{geojson}");
         }
-        Commands::Query {
+        Commands::Journey {
             network_path,
-            lat,
-            lon,
+            positionals,
+            mut origin,
+            options,
+        } => {
+            let [date, time, to] = origin.take_point(positionals).expect("Invalid arguments");
+            let date: NaiveDate = date.parse().expect("Invalid date");
+            let time: NaiveTime = time.parse().expect("Invalid time");
+            let to: u64 = to.parse().expect("Invalid destination stop ID");
+            let network = TransportNetwork::load(network_path).expect("Failed to load network");
+            let origin = origin.resolve(&network).expect("Invalid origin");
+            let options = QueryOptions::try_from(options).expect("Invalid query options");
+            options.validate().expect("Invalid query options");
+            match network.journey(&origin, date, time, StopId::new(to), &options) {
+                Some(journey) => print!("{journey}"),
+                None => println!("No journey found to stop {to}"),
+            }
+        }
+        Commands::Departures {
+            network_path,
+            stop,
             date,
             time,
+            window,
+            options,
         } => {
             let network = TransportNetwork::load(network_path).expect("Failed to load network");
-            let geojson =
-                run_query(&network, lat, lon, date, time).expect("Failed to execute query");
-            println!("{geojson}");
+            let window = TimeDelta::minutes(window.into());
+            let options = QueryOptions::try_from(options).expect("Invalid query options");
+            options.validate().expect("Invalid query options");
+            let board = network
+                .departures(StopId::new(stop), date, time, window, &options)
+                .expect("Invalid departure window");
+            match board {
+                Some(board) => print!("{board}"),
+                None => println!("No stop with ID {stop}"),
+            }
+        }
+        Commands::Stops {
+            network_path,
+            query,
+            limit,
+        } => {
+            let network = TransportNetwork::load(network_path).expect("Failed to load network");
+            for m in network.search_stops(&query, limit) {
+                if m.matched == m.name {
+                    println!("{} {}", m.stop_id.index(), m.name);
+                } else {
+                    println!("{} {} (matched {})", m.stop_id.index(), m.name, m.matched);
+                }
+            }
         }
         Commands::Serve { network_path } => {
             let now = std::time::Instant::now();
@@ -86,6 +467,10 @@ async fn main() {
 
             let app = Router::new()
                 .route("/isochrone", get(isochrone))
+                .route("/isochrone/polygons", get(polygon_isochrone))
+                .route("/journey", get(journey))
+                .route("/departures", get(departures))
+                .route("/stops/search", get(search_stops))
                 .layer(
                     CorsLayer::new()
                         .allow_origin([
@@ -109,68 +494,328 @@ async fn main() {
     }
 }
 
-fn import_timetable(
-    timetable_path: impl AsRef<Path>,
-    network_path: impl AsRef<Path>,
-) -> anyhow::Result<()> {
+fn import_timetables(args: ImportArgs) -> anyhow::Result<()> {
+    let (timetable_paths, output) = args.timetables_and_output()?;
+    let formats = &args.format;
+
+    ensure!(
+        formats.len() == 1 || formats.len() == timetable_paths.len(),
+        "expected one format, or one per timetable, but got {} formats for {} timetables",
+        formats.len(),
+        timetable_paths.len()
+    );
+    let cif_count = timetable_paths
+        .iter()
+        .zip(formats.iter().cycle())
+        .filter(|(_, f)| matches!(f, TimetableFormat::Cif))
+        .count();
+    ensure!(
+        args.update.is_empty() || cif_count == 1,
+        "updates need exactly one CIF timetable to apply to, but got {cif_count}"
+    );
+
+    let station_overrides = match &args.stations {
+        Some(path) => read_station_overrides(path)?,
+        None => vec![],
+    };
+    let tiploc_mappings = match &args.tiploc_mappings {
+        Some(path) => read_tiploc_mappings(path)?,
+        None => HashMap::new(),
+    };
+
+    // A lone timetable has nothing to be linked to, so skips the merge
+    let network = match (timetable_paths, &formats[..]) {
+        ([path], [format]) if args.exclude.is_empty() => match format {
+            TimetableFormat::Cif => {
+                let timetable = read_cif(path, &args.update)?;
+
+                let now = std::time::Instant::now();
+                info!("Adapting to transport network");
+                let network =
+                    TransportNetwork::from_cif(&timetable, &station_overrides, &tiploc_mappings)?;
+                info!("Done in {:?}", now.elapsed());
+                network
+            }
+            TimetableFormat::Gtfs => {
+                let feed = read_gtfs(path)?;
+
+                let now = std::time::Instant::now();
+                info!("Adapting to transport network");
+                let network = TransportNetwork::from_gtfs(&feed)?;
+                info!("Done in {:?}", now.elapsed());
+                network
+            }
+        },
+        _ => {
+            let mut merged =
+                MergedAdapter::new(args.link_radius, args.exclude.iter().copied().collect());
+            for (path, &format) in timetable_paths.iter().zip(formats.iter().cycle()) {
+                match format {
+                    TimetableFormat::Cif => {
+                        let timetable = read_cif(path, &args.update)?;
+
+                        let now = std::time::Instant::now();
+                        info!("Adapting to transport network");
+                        merged.add(&CifAdapter::new(
+                            &timetable,
+                            &station_overrides,
+                            &tiploc_mappings,
+                        )?)?;
+                        info!("Done in {:?}", now.elapsed());
+                    }
+                    TimetableFormat::Gtfs => {
+                        let feed = read_gtfs(path)?;
+
+                        let now = std::time::Instant::now();
+                        info!("Adapting to transport network");
+                        merged.add(&GtfsAdapter::new(&feed)?)?;
+                        info!("Done in {:?}", now.elapsed());
+                    }
+                }
+            }
+
+            let now = std::time::Instant::now();
+            info!("Linking timetables");
+            let network = TransportNetwork::from_adapter(&merged)?;
+            info!("Done in {:?}", now.elapsed());
+            network
+        }
+    };
+
     let now = std::time::Instant::now();
-    info!("Reading timetable");
-    let timetable = CifTimetable::read(timetable_path)?;
+    info!("Saving network");
+    network.save(output)?;
     info!("Done in {:?}", now.elapsed());
 
+    Ok(())
+}
+
+fn read_cif(path: &Path, updates: &[PathBuf]) -> anyhow::Result<CifTimetable> {
     let now = std::time::Instant::now();
-    info!("Adapting to transport network");
-    let network = TransportNetwork::try_from(&timetable)?;
+    info!("Reading CIF timetable {path:?}");
+    let mut timetable = CifTimetable::read(path)?;
+    for update_path in updates {
+        info!("Applying update {update_path:?}");
+        timetable.apply_update(update_path)?;
+    }
     info!("Done in {:?}", now.elapsed());
 
+    Ok(timetable)
+}
+
+fn read_gtfs(path: &Path) -> anyhow::Result<GtfsFeed> {
     let now = std::time::Instant::now();
-    info!("Saving network");
-    network.save(network_path)?;
+    info!("Reading GTFS feed {path:?}");
+    let feed = GtfsFeed::read(path)?;
     info!("Done in {:?}", now.elapsed());
 
-    Ok(())
+    Ok(feed)
 }
 
 #[derive(Deserialize)]
 struct IsochroneParams {
-    lat: f64,
-    lon: f64,
     date: NaiveDate,
     time: NaiveTime,
+    until: Option<NaiveTime>,
+    #[serde(default)]
+    arrive_by: bool,
+}
+
+impl IsochroneParams {
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(until) = self.until {
+            ensure!(!self.arrive_by, "until can't be combined with arrive_by");
+            ensure!(
+                until >= self.time,
+                "departure window ends ({until}) before it starts ({})",
+                self.time
+            );
+        }
+        Ok(())
+    }
 }
 
 async fn isochrone(
     Query(params): Query<IsochroneParams>,
+    Query(origin): Query<OriginArgs>,
+    Query(options): Query<QueryArgs>,
     State(network): State<Arc<TransportNetwork>>,
 ) -> Result<Json<FeatureCollection>, StatusCode> {
-    let IsochroneParams {
-        lat,
-        lon,
+    if params.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let origin = origin
+        .resolve(&network)
+        .map_err(|_e| StatusCode::BAD_REQUEST)?;
+    let options = QueryOptions::try_from(options).map_err(|_e| StatusCode::BAD_REQUEST)?;
+    if options.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    run_query(&network, &origin, params, &options)
+        .map(Json)
+        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn polygon_isochrone(
+    Query(params): Query<IsochroneParams>,
+    Query(origin): Query<OriginArgs>,
+    Query(options): Query<QueryArgs>,
+    Query(polygon_options): Query<PolygonArgs>,
+    State(network): State<Arc<TransportNetwork>>,
+) -> Result<Json<FeatureCollection>, StatusCode> {
+    let options = QueryOptions::try_from(options).map_err(|_e| StatusCode::BAD_REQUEST)?;
+    let polygon_options = PolygonOptions::from(polygon_options);
+    if params.until.is_some()
+        || params.arrive_by
+        || options.validate().is_err()
+        || polygon_options.validate().is_err()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let origin = origin
+        .resolve(&network)
+        .map_err(|_e| StatusCode::BAD_REQUEST)?;
+
+    run_polygon_query(&network, &origin, params, &options, &polygon_options)
+        .map(Json)
+        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Deserialize)]
+struct JourneyParams {
+    date: NaiveDate,
+    time: NaiveTime,
+    to: u64,
+}
+
+async fn journey(
+    Query(params): Query<JourneyParams>,
+    Query(origin): Query<OriginArgs>,
+    Query(options): Query<QueryArgs>,
+    State(network): State<Arc<TransportNetwork>>,
+) -> Result<Json<Journey>, StatusCode> {
+    let JourneyParams { date, time, to } = params;
+    let origin = origin
+        .resolve(&network)
+        .map_err(|_e| StatusCode::BAD_REQUEST)?;
+    let options = QueryOptions::try_from(options).map_err(|_e| StatusCode::BAD_REQUEST)?;
+    if options.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let now = std::time::Instant::now();
+    info!("Querying network for a journey from {origin} to stop {to} on {date} at {time}");
+    let journey = network.journey(&origin, date, time, StopId::new(to), &options);
+    info!("Done in {:?}", now.elapsed());
+
+    journey.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct DeparturesParams {
+    stop: u64,
+    date: NaiveDate,
+    time: NaiveTime,
+    /// Minutes ahead of `time`, an hour by default.
+    window: Option<u32>,
+}
+
+async fn departures(
+    Query(params): Query<DeparturesParams>,
+    Query(options): Query<QueryArgs>,
+    State(network): State<Arc<TransportNetwork>>,
+) -> Result<Json<DepartureBoard>, StatusCode> {
+    let DeparturesParams {
+        stop,
         date,
         time,
+        window,
     } = params;
+    let window = TimeDelta::minutes(window.unwrap_or(60).into());
+    let options = QueryOptions::try_from(options).map_err(|_e| StatusCode::BAD_REQUEST)?;
+    if options.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
     let now = std::time::Instant::now();
-    info!("Querying network for arrival times starting from ({lat}, {lon}) on {date} at {time}");
-    let arrival_times = network.query_lat_lon(lat, lon, date, time);
-    let features = to_feature_collection(&arrival_times);
+    info!("Querying network for departures from stop {stop} on {date} at {time}");
+    let board = network
+        .departures(StopId::new(stop), date, time, window, &options)
+        .map_err(|_e| StatusCode::BAD_REQUEST)?;
     info!("Done in {:?}", now.elapsed());
 
-    features
-        .map(Json)
-        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)
+    board.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn search_stops(
+    Query(params): Query<SearchParams>,
+    State(network): State<Arc<TransportNetwork>>,
+) -> Json<Vec<StopMatch>> {
+    Json(network.search_stops(&params.q, params.limit.unwrap_or(10)))
 }
 
 fn run_query(
     network: &TransportNetwork,
-    lat: f64,
-    lon: f64,
-    date: NaiveDate,
-    time: NaiveTime,
+    origin: &Origin,
+    params: IsochroneParams,
+    options: &QueryOptions,
 ) -> anyhow::Result<FeatureCollection> {
+    params.validate()?;
+    options.validate()?;
+    let IsochroneParams {
+        date,
+        time,
+        until,
+        arrive_by,
+    } = params;
+
+    let now = std::time::Instant::now();
+    if arrive_by {
+        info!("Querying network for departure times to reach {origin} on {date} by {time}");
+        let departure_times = network.query_arrive_by(origin, date, time, options);
+        info!("Done in {:?}", now.elapsed());
+        to_feature_collection(&departure_times)
+    } else if let Some(until) = until {
+        info!(
+            "Querying network for journey profiles starting from {origin} on {date} between {time} and {until}"
+        );
+        let profiles = network.profile(origin, date, time, until, options)?;
+        info!("Done in {:?}", now.elapsed());
+        to_feature_collection(&profiles)
+    } else {
+        info!("Querying network for arrival times starting from {origin} on {date} at {time}");
+        let arrival_times = network.query(origin, date, time, options);
+        info!("Done in {:?}", now.elapsed());
+        to_feature_collection(&arrival_times)
+    }
+}
+
+fn run_polygon_query(
+    network: &TransportNetwork,
+    origin: &Origin,
+    params: IsochroneParams,
+    options: &QueryOptions,
+    polygon_options: &PolygonOptions,
+) -> anyhow::Result<FeatureCollection> {
+    let IsochroneParams { date, time, .. } = params;
+    options.validate()?;
+    polygon_options.validate()?;
+
     let now = std::time::Instant::now();
-    info!("Querying network for arrival times starting from ({lat}, {lon}) on {date} at {time}");
-    let arrival_times = network.query_lat_lon(lat, lon, date, time);
+    info!("Querying network for isochrone polygons starting from {origin} on {date} at {time}");
+    let arrival_times = network.query(origin, date, time, options);
+    let bands = isochrone_polygons(
+        network.origin_point(origin),
+        &arrival_times,
+        polygon_options,
+    );
     info!("Done in {:?}", now.elapsed());
-    to_feature_collection(&arrival_times)
+    to_feature_collection(&bands)
 }
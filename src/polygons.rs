@@ -0,0 +1,203 @@
+use std::f64::consts::TAU;
+
+use anyhow::ensure;
+use geo::unary_union;
+use geo_types::{LineString, MultiPolygon, Point, Polygon};
+use geojson::ser::serialize_geometry;
+use serde::Serialize;
+
+use crate::csa::{ArrivalTime, R_EARTH_M, WALKING_SPEED_M_S};
+
+/// Number of vertices used to approximate each walking circle.
+const CIRCLE_VERTICES: usize = 32;
+
+/// Largest band edge and walking speed allowed, which keep the walking circles far
+/// smaller than the Earth.
+const MAX_BAND_MINUTES: u32 = 24 * 60;
+const MAX_WALKING_SPEED_M_S: f64 = 10.0;
+
+pub struct PolygonOptions {
+    /// Upper edge of each band, in minutes.
+    pub bands: Vec<u32>,
+    /// Walking speed from the station to the final destination, in metres per second.
+    pub walking_speed: f64,
+}
+
+impl Default for PolygonOptions {
+    fn default() -> Self {
+        Self {
+            bands: vec![30, 60, 90, 120],
+            walking_speed: WALKING_SPEED_M_S,
+        }
+    }
+}
+
+impl PolygonOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.bands.is_empty(), "at least one band is required");
+        ensure!(
+            self.bands.iter().all(|&b| b > 0),
+            "band edges must be positive"
+        );
+        ensure!(
+            self.bands.iter().all(|&b| b <= MAX_BAND_MINUTES),
+            "band edges must be at most {MAX_BAND_MINUTES} minutes"
+        );
+        ensure!(
+            self.walking_speed.is_finite() && self.walking_speed > 0.0,
+            "walking speed must be positive"
+        );
+        ensure!(
+            self.walking_speed <= MAX_WALKING_SPEED_M_S,
+            "walking speed must be at most {MAX_WALKING_SPEED_M_S} metres per second"
+        );
+        Ok(())
+    }
+}
+
+/// The area reachable within `minutes` of leaving the origin.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IsochroneBand {
+    pub minutes: u32,
+    #[serde(serialize_with = "serialize_geometry")]
+    pub geometry: MultiPolygon<f64>,
+}
+
+/// Turns station arrival times into one polygon per band, covering everywhere that
+/// can be walked to from the origin or from a station in the time left over.
+pub fn isochrone_polygons(
+    origin: Point<f64>,
+    arrival_times: &[ArrivalTime],
+    options: &PolygonOptions,
+) -> Vec<IsochroneBand> {
+    let mut bands = options.bands.clone();
+    bands.sort_unstable();
+    bands.dedup();
+
+    bands
+        .into_iter()
+        .map(|minutes| {
+            let budget = i64::from(minutes) * 60;
+            let circles: Vec<Polygon<f64>> = std::iter::once((origin, 0))
+                .chain(arrival_times.iter().map(|a| (a.geometry, a.arrival_time)))
+                .filter(|&(_, arrival)| arrival < budget)
+                .map(|(centre, arrival)| {
+                    let radius = (budget - arrival) as f64 * options.walking_speed;
+                    circle(centre, radius)
+                })
+                .collect();
+
+            IsochroneBand {
+                minutes,
+                geometry: unary_union(&circles),
+            }
+        })
+        .collect()
+}
+
+/// Approximates a circle of `radius_m` metres around `centre` on the Earth's surface.
+fn circle(centre: Point<f64>, radius_m: f64) -> Polygon<f64> {
+    let (lon, lat) = (centre.x(), centre.y());
+    let angular_radius = radius_m / R_EARTH_M;
+    let (lat_r, lon_r) = (lat.to_radians(), lon.to_radians());
+
+    let ring: LineString<f64> = (0..=CIRCLE_VERTICES)
+        .map(|i| {
+            let bearing = TAU * (i % CIRCLE_VERTICES) as f64 / CIRCLE_VERTICES as f64;
+            let lat2 = (lat_r.sin() * angular_radius.cos()
+                + lat_r.cos() * angular_radius.sin() * bearing.cos())
+            .asin();
+            let lon2 = lon_r
+                + (bearing.sin() * angular_radius.sin() * lat_r.cos())
+                    .atan2(angular_radius.cos() - lat_r.sin() * lat2.sin());
+            (lon2.to_degrees(), lat2.to_degrees())
+        })
+        .collect();
+
+    Polygon::new(ring, vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Contains, Distance, Haversine};
+
+    use super::*;
+    use crate::csa::StopId;
+
+    const ORIGIN: Point<f64> = Point(geo_types::Coord { x: -1.0, y: 51.0 });
+
+    /// The point `metres` north of `from`.
+    fn north(from: Point<f64>, metres: f64) -> Point<f64> {
+        Point::new(from.x(), from.y() + (metres / R_EARTH_M).to_degrees())
+    }
+
+    fn arrival(geometry: Point<f64>, minutes: i64) -> ArrivalTime {
+        ArrivalTime {
+            stop_id: StopId::new(0),
+            stop_name: "Station".to_owned(),
+            arrival_time: minutes * 60,
+            changes: 0,
+            arrived_by: None,
+            geometry,
+        }
+    }
+
+    fn options(bands: Vec<u32>) -> PolygonOptions {
+        PolygonOptions {
+            bands,
+            ..PolygonOptions::default()
+        }
+    }
+
+    #[test]
+    fn approximates_circles_of_the_given_radius() {
+        let circle = circle(ORIGIN, 1000.0);
+
+        for vertex in circle.exterior().points() {
+            let distance = Haversine.distance(ORIGIN, vertex);
+            assert!((distance - 1000.0).abs() < 0.1, "vertex {distance} m away");
+        }
+    }
+
+    #[test]
+    fn covers_walking_from_the_origin_and_from_stations_in_the_time_left() {
+        // Ten minutes' walk is 840 metres
+        let station = north(ORIGIN, 10_000.0);
+        let arrivals = [arrival(station, 20)];
+
+        let bands = isochrone_polygons(ORIGIN, &arrivals, &options(vec![10, 30]));
+
+        let [ten, thirty] = &bands[..] else {
+            panic!("expected two bands, got {}", bands.len());
+        };
+        assert!(ten.geometry.contains(&north(ORIGIN, 800.0)));
+        assert!(!ten.geometry.contains(&north(ORIGIN, 900.0)));
+        assert!(!ten.geometry.contains(&station));
+        assert!(thirty.geometry.contains(&north(station, 800.0)));
+        assert!(!thirty.geometry.contains(&north(station, 900.0)));
+        assert!(!thirty.geometry.contains(&north(ORIGIN, 5000.0)));
+    }
+
+    #[test]
+    fn sorts_and_dedups_bands() {
+        let bands = isochrone_polygons(ORIGIN, &[], &options(vec![60, 30, 60]));
+
+        let minutes: Vec<u32> = bands.iter().map(|b| b.minutes).collect();
+        assert_eq!(minutes, [30, 60]);
+    }
+
+    #[test]
+    fn rejects_bands_and_speeds_out_of_range() {
+        assert!(options(vec![30, MAX_BAND_MINUTES]).validate().is_ok());
+        assert!(options(vec![]).validate().is_err());
+        assert!(options(vec![0, 30]).validate().is_err());
+        assert!(options(vec![MAX_BAND_MINUTES + 1]).validate().is_err());
+
+        let fast = PolygonOptions {
+            walking_speed: MAX_WALKING_SPEED_M_S * 2.0,
+            ..PolygonOptions::default()
+        };
+        assert!(fast.validate().is_err());
+    }
+}